@@ -1,5 +1,5 @@
 
-use std::{hash::Hash};
+use std::{hash::Hash, rc::Rc};
 
 use crate::lexer::{Token};
 
@@ -7,13 +7,19 @@ use crate::lexer::{Token};
 pub enum Statement {
     LetStatement{
         token: Token,
-        name: Expression,
+        name: Pattern,
         value: Expression
     },
     ReturnStatement {
         token: Token,
         return_value: Expression
     },
+    /// `throw value`: raises an error from a string, or from a hash with a
+    /// `message` and optionally a `kind`.
+    ThrowStatement {
+        token: Token,
+        value: Expression
+    },
     ExpressionStatement {
         token: Token,
         expression: Expression
@@ -24,7 +30,19 @@ pub enum Statement {
     }
 }
 
-#[derive(Debug)]
+impl Statement {
+    pub fn token(&self) -> &Token {
+        match self {
+            Statement::LetStatement{token, ..} => token,
+            Statement::ReturnStatement{token, ..} => token,
+            Statement::ThrowStatement{token, ..} => token,
+            Statement::ExpressionStatement{token, ..} => token,
+            Statement::BlockStatment{token, ..} => token
+        }
+    }
+}
+
+#[derive(Debug, Default)]
 pub struct Program {
     pub statements: Vec<Statement>
 }
@@ -37,6 +55,102 @@ impl Program {
     }
 }
 
+// The derived drop glue would recurse once per level of nesting, so a
+// program takes its tree apart with an explicit stack instead.
+impl Drop for Program {
+    fn drop(&mut self) {
+        let mut statements = std::mem::take(&mut self.statements);
+        let mut expressions = Vec::new();
+        let mut patterns = Vec::new();
+        loop {
+            if let Some(statement) = statements.pop() {
+                match statement {
+                    Statement::LetStatement{token:_, name, value} => {
+                        patterns.push(name);
+                        expressions.push(value);
+                    },
+                    Statement::ReturnStatement{token:_, return_value: value}
+                    | Statement::ThrowStatement{token:_, value}
+                    | Statement::ExpressionStatement{token:_, expression: value} => expressions.push(value),
+                    Statement::BlockStatment{token:_, statements: block} => statements.extend(block)
+                }
+            } else if let Some(expression) = expressions.pop() {
+                match expression {
+                    Expression::TemplateLiteral{token:_, strings:_, values: elements}
+                    | Expression::ArrayLiteral{token:_, elements} => expressions.extend(elements),
+                    Expression::HashLiteral{token:_, pairs} => {
+                        for (key, value) in pairs {
+                            expressions.push(key);
+                            expressions.push(value);
+                        }
+                    },
+                    Expression::IndexExpression{token:_, left, index: right}
+                    | Expression::Named{token:_, name: left, value: right}
+                    | Expression::InfixExpression{token:_, left, operator:_, right} => {
+                        expressions.push(*left);
+                        expressions.push(*right);
+                    },
+                    Expression::SliceExpression{token:_, left, start, end} => {
+                        expressions.push(*left);
+                        expressions.extend(start.map(|start| *start));
+                        expressions.extend(end.map(|end| *end));
+                    },
+                    Expression::Spread{token:_, value}
+                    | Expression::Propagate{token:_, value}
+                    | Expression::MemberExpression{token:_, object: value, property:_}
+                    | Expression::PrefixExpression{token:_, operator:_, right: value} => expressions.push(*value),
+                    Expression::IfExpression{token:_, condition, consequence, alternative} => {
+                        expressions.push(*condition);
+                        statements.push(*consequence);
+                        statements.extend(alternative.map(|alternative| *alternative));
+                    },
+                    Expression::FunctionLiteral{token:_, parameters, body} => {
+                        expressions.extend(parameters);
+                        // Function values made from the literal may still
+                        // share its body.
+                        statements.extend(Rc::try_unwrap(body).ok());
+                    },
+                    Expression::CallExpression{token:_, function, arguments} => {
+                        expressions.push(*function);
+                        expressions.extend(arguments);
+                    },
+                    Expression::MatchExpression{token:_, subject, arms} => {
+                        expressions.push(*subject);
+                        for arm in arms {
+                            patterns.push(arm.pattern);
+                            expressions.extend(arm.guard);
+                            statements.push(*arm.body);
+                        }
+                    },
+                    Expression::TryExpression{token:_, body, catch, finally} => {
+                        statements.push(*body);
+                        if let Some(catch) = catch {
+                            patterns.extend(catch.parameter);
+                            statements.push(*catch.body);
+                        }
+                        statements.extend(finally.map(|finally| *finally));
+                    },
+                    _ => {}
+                }
+            } else if let Some(pattern) = patterns.pop() {
+                match pattern {
+                    Pattern::Binding(expression) | Pattern::Literal(expression) => expressions.push(expression),
+                    Pattern::Array{token:_, elements, rest} => {
+                        patterns.extend(elements);
+                        patterns.extend(rest.map(|rest| *rest));
+                    },
+                    Pattern::Hash{token:_, entries} => patterns.extend(entries.into_iter().map(|(_, pattern)| pattern)),
+                    Pattern::Variant{token:_, name:_, value} => patterns.extend(value.map(|value| *value)),
+                    Pattern::Or{token:_, alternatives} => patterns.extend(alternatives),
+                    Pattern::Wildcard(_) => {}
+                }
+            } else {
+                break;
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Expression {
     Nope,
@@ -44,6 +158,14 @@ pub enum Expression {
         token: Token,
         value: String
     },
+    /// An identifier the resolver has bound to a function parameter or
+    /// local: `depth` frames up from the current one, at index `slot`.
+    Local {
+        token: Token,
+        value: String,
+        depth: usize,
+        slot: usize
+    },
     IntegerLiteral {
         token: Token,
         value: i64
@@ -52,6 +174,13 @@ pub enum Expression {
         token: Token,
         value: String
     },
+    /// `"text ${value} text"`: `strings` holds the text around the
+    /// interpolated `values`, so it always has one more entry.
+    TemplateLiteral {
+        token: Token,
+        strings: Vec<String>,
+        values: Vec<Expression>
+    },
     ArrayLiteral {
         token: Token,
         elements: Vec<Expression>
@@ -65,6 +194,38 @@ pub enum Expression {
         left: Box<Expression>,
         index: Box<Expression>
     },
+    /// `left[start:end]`: the elements of an array or the characters of a
+    /// string from `start` up to `end`. Either bound may be left out.
+    SliceExpression {
+        token: Token,
+        left: Box<Expression>,
+        start: Option<Box<Expression>>,
+        end: Option<Box<Expression>>
+    },
+    /// `...value`: spreads an array into call arguments or an array
+    /// literal, or collects the remaining arguments as a rest parameter.
+    Spread {
+        token: Token,
+        value: Box<Expression>
+    },
+    /// `name = value`: a parameter with a default value, or a keyword
+    /// argument in a call.
+    Named {
+        token: Token,
+        name: Box<Expression>,
+        value: Box<Expression>
+    },
+    /// `value?`: the value inside an `Ok` or `Some`, or else returns the
+    /// `Err` or `None` from the enclosing function.
+    Propagate {
+        token: Token,
+        value: Box<Expression>
+    },
+    MemberExpression {
+        token: Token,
+        object: Box<Expression>,
+        property: String
+    },
     PrefixExpression {
         token: Token,
         operator: String,
@@ -89,11 +250,126 @@ pub enum Expression {
     FunctionLiteral {
         token: Token,
         parameters: Vec<Expression>,
-        body: Box<Statement>
+        /// Shared with the function values made from this literal.
+        body: Rc<Statement>
     },
     CallExpression {
         token: Token,
         function: Box<Expression>,
         arguments: Vec<Expression>
+    },
+    /// `match (subject) { pattern => body, ... }`: the value of the first
+    /// arm whose pattern matches and whose guard, if any, is truthy.
+    MatchExpression {
+        token: Token,
+        subject: Box<Expression>,
+        arms: Vec<MatchArm>
+    },
+    /// `try { } catch (e) { } finally { }`: the value of the `try` block,
+    /// or of the `catch` block if an error was raised. The `finally` block
+    /// runs last whatever happened and its value is dropped.
+    TryExpression {
+        token: Token,
+        body: Box<Statement>,
+        catch: Option<Box<Catch>>,
+        finally: Option<Box<Statement>>
     }
-}
\ No newline at end of file
+}
+
+/// `catch (pattern) { }`, where the pattern takes apart the error hash.
+/// Without a parameter the error is dropped.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Catch {
+    pub parameter: Option<Pattern>,
+    pub body: Box<Statement>
+}
+
+/// `pattern if guard => body`. The body is an expression statement, or a
+/// block when it starts with `{`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub guard: Option<Expression>,
+    pub body: Box<Statement>
+}
+
+impl Expression {
+    /// The identifier a function parameter binds: the parameter itself, or
+    /// the name of a `name = default` or `...rest` parameter.
+    pub fn parameter_name(&self) -> &Expression {
+        match self {
+            Expression::Named{name, ..} => name,
+            Expression::Spread{value, ..} => value,
+            parameter => parameter
+        }
+    }
+}
+
+/// The left-hand side of a `let` or a `match` arm, which can take a value
+/// apart.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Pattern {
+    /// Binds the whole value to an `Identifier`, or a `Local` once resolved.
+    Binding(Expression),
+    /// `_`: matches anything and binds nothing.
+    Wildcard(Token),
+    /// An integer, string or boolean literal, matched by equality.
+    Literal(Expression),
+    /// `[first, second, ...rest]`: needs an array with exactly as many
+    /// elements as patterns, or at least as many when there is a rest.
+    Array {
+        token: Token,
+        elements: Vec<Pattern>,
+        rest: Option<Box<Pattern>>
+    },
+    /// `{name, age: years}`: looks up each key in a hash, which must have
+    /// all of them, and matches the value against its pattern.
+    Hash {
+        token: Token,
+        entries: Vec<(String, Pattern)>
+    },
+    /// `Ok(pattern)`, `Err(pattern)`, `Some(pattern)` or `None`.
+    Variant {
+        token: Token,
+        name: String,
+        value: Option<Box<Pattern>>
+    },
+    /// `a | b`: the first alternative that matches. Every alternative binds
+    /// the same names.
+    Or {
+        token: Token,
+        alternatives: Vec<Pattern>
+    }
+}
+
+impl Pattern {
+    /// The names the pattern binds, in source order.
+    pub fn bindings(&self) -> Vec<&Expression> {
+        let mut names = Vec::new();
+        let mut pending = vec![self];
+        while let Some(pattern) = pending.pop() {
+            match pattern {
+                Pattern::Binding(name) => names.push(name),
+                Pattern::Wildcard(_) | Pattern::Literal(_) => {},
+                Pattern::Array{token:_, elements, rest} => {
+                    pending.extend(elements.iter().chain(rest.as_deref()).rev());
+                },
+                Pattern::Hash{token:_, entries} => {
+                    pending.extend(entries.iter().rev().map(|(_, pattern)| pattern));
+                },
+                Pattern::Variant{token:_, name:_, value} => pending.extend(value.as_deref()),
+                Pattern::Or{token:_, alternatives} => pending.extend(alternatives.first())
+            }
+        }
+        names
+    }
+
+    /// Whether the pattern matches every value.
+    pub fn is_irrefutable(&self) -> bool {
+        match self {
+            Pattern::Binding(_) | Pattern::Wildcard(_) => true,
+            Pattern::Or{token:_, alternatives} => alternatives.iter().any(Pattern::is_irrefutable),
+            _ => false
+        }
+    }
+}
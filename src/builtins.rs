@@ -1,108 +1,572 @@
-use std::{vec};
+use std::collections::HashSet;
 use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{env, fs};
 
-use crate::{environment::Environment, object::{Builtin, Object}};
+use crate::convert::{Callable, IntoObject, NativeFunction, RuntimeError};
+use crate::evaluator::{apply_function, eval_slice_expression};
+use crate::{environment::Environment, limits, object::{Builtin, Object, EXIT_ERROR, PERMISSION_ERROR}};
 
+/// A group of builtins the embedder can grant to scripts. Only `Pure`
+/// builtins are free of side effects on the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Capability {
+    Pure,
+    /// Printing to stdout with `puts`.
+    Io,
+    FsRead,
+    FsWrite,
+    Env,
+    Clock,
+    Process
+}
+
+impl Capability {
+    pub const ALL: &'static [Capability] = &[
+        Capability::Pure,
+        Capability::Io,
+        Capability::FsRead,
+        Capability::FsWrite,
+        Capability::Env,
+        Capability::Clock,
+        Capability::Process
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Capability::Pure => "pure",
+            Capability::Io => "io",
+            Capability::FsRead => "fs-read",
+            Capability::FsWrite => "fs-write",
+            Capability::Env => "env",
+            Capability::Clock => "clock",
+            Capability::Process => "process"
+        }
+    }
+}
+
+/// The root scope of an interpreter. Builtins of capabilities that were
+/// not granted are still defined, but calling them is a permission error.
 pub struct Builtins {
-    pub builtins: Environment
+    pub builtins: Environment,
+    capabilities: Vec<Capability>
 }
 
+impl Default for Builtins {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Builtins {
+    /// Only the pure builtins, the sandbox for untrusted scripts.
     pub fn new() -> Self {
-        let mut builtins = Environment::new();
-        let len = Rc::new(Builtin{
-            func: Box::new(|args| {
-                let s = args[0].clone();
-                if let Object::String{
-                    value
-                } = s {
-                    return Object::Integer{value: value.len() as i64};
-                }
-                if let Object::Array{elements} = s {
-                    return Object::Integer{value: elements.len() as i64};
-                }
-                Object::Null
-            })
-        });
+        Builtins::with_capabilities(&[Capability::Pure])
+    }
 
-        let first = Rc::new(Builtin{
-            func: Box::new(|args| {
-                let s = args[0].clone();
-                if let Object::Array{elements} = s {
-                    if elements.len() > 0 {
-                        return elements.first().unwrap().clone();
-                    }
-                    return Object::Null;
-                }
-                Object::Null
-            })
-        });
+    /// The builtins of the given capabilities.
+    ///
+    /// ```
+    /// use monkey::{builtins::{Builtins, Capability}, Interpreter};
+    ///
+    /// let builtins = Builtins::with_capabilities(&[Capability::Pure, Capability::Clock]);
+    /// let mut interpreter = Interpreter::with_builtins(builtins);
+    /// assert!(interpreter.eval_str("now() > 0").is_ok());
+    /// let err = interpreter.eval_str("read_file(\"/etc/passwd\")").unwrap_err();
+    /// assert_eq!(err.to_string(), "runtime error: permission denied: `read_file` needs the fs-read capability");
+    /// ```
+    pub fn with_capabilities(capabilities: &[Capability]) -> Self {
+        let mut builtins = Builtins {
+            builtins: Environment::new(),
+            capabilities: capabilities.to_vec()
+        };
 
-        let last = Rc::new(Builtin{
-            func: Box::new(|args| {
-                let s = args[0].clone();
-                if let Object::Array{elements} = s {
-                    if elements.len() > 0 {
-                        return elements.last().unwrap().clone();
-                    }
-                    return Object::Null;
-                }
-                Object::Null
-            })
+        builtins.register(Capability::Pure, "len", |value: Object| match &value {
+            Object::String{value} => Ok(value.chars().count()),
+            Object::Array{elements} => Ok(elements.len()),
+            other => Err(RuntimeError(format!("argument 1 to `len`: expected STRING or ARRAY, got {}", other.type_name())))
+        });
+        builtins.register(Capability::Pure, "first", |elements: Vec<Object>| elements.first().cloned().unwrap_or_default());
+        builtins.register(Capability::Pure, "last", |elements: Vec<Object>| elements.last().cloned().unwrap_or_default());
+        builtins.register(Capability::Pure, "rest", |mut elements: Vec<Object>| {
+            if elements.is_empty() {
+                return Object::Null;
+            }
+            elements.remove(0);
+            limits::track(Object::Array{elements})
+        });
+        builtins.register(Capability::Pure, "push", |mut elements: Vec<Object>, element: Object| {
+            settle(limits::reserve_elements(elements.len() + 1).map(|_| {
+                elements.push(element);
+                elements
+            }))
         });
 
-        let rest = Rc::new(Builtin{
-            func: Box::new(|args| {
-                let mut s = args[0].clone();
-                if let Object::Array{elements} = &mut s {
-                    
-                    if elements.len() > 0 {
-                        elements.remove(0);
-                        let new_elements = std::mem::replace(elements, vec![]);
-                        return Object::Array {
-                            elements: new_elements
-                        };
-                    }
-                    return Object::Null;
-                }
-                Object::Null
-            })
+
+        // String positions and lengths count characters, not bytes.
+        builtins.register(Capability::Pure, "split", |value: String, separator: String| {
+            if separator.is_empty() {
+                return Err(RuntimeError::from("separator of `split` must not be empty"));
+            }
+            Ok(value.split(separator.as_str()).map(str::to_string).collect::<Vec<String>>())
+        });
+        builtins.register(Capability::Pure, "join", |elements: Vec<Object>, separator: String| {
+            elements.iter().map(Object::to_string).collect::<Vec<String>>().join(&separator)
+        });
+        builtins.register(Capability::Pure, "trim", |value: String| value.trim().to_string());
+        builtins.register(Capability::Pure, "upper", |value: String| value.to_uppercase());
+        builtins.register(Capability::Pure, "lower", |value: String| value.to_lowercase());
+        builtins.register(Capability::Pure, "replace", |value: String, from: String, to: String| {
+            if from.is_empty() {
+                return Object::new_error("pattern of `replace` must not be empty".to_string());
+            }
+            let count = value.matches(from.as_str()).count();
+            let bytes = count.checked_mul(to.len()).and_then(|added| (value.len() - count * from.len()).checked_add(added));
+            settle(reserve_string("replace", bytes).map(|_| value.replace(&from, &to)))
+        });
+        builtins.register(Capability::Pure, "contains", |value: String, part: String| value.contains(&part));
+        builtins.register(Capability::Pure, "starts_with", |value: String, prefix: String| value.starts_with(&prefix));
+        builtins.register(Capability::Pure, "ends_with", |value: String, suffix: String| value.ends_with(&suffix));
+        builtins.register(Capability::Pure, "index_of", |value: String, part: String| {
+            value.find(&part).map(|offset| value[..offset].chars().count())
+        });
+        builtins.register(Capability::Pure, "substr", |value: String, start: usize, length: usize| {
+            value.chars().skip(start).take(length).collect::<String>()
+        });
+        builtins.register(Capability::Pure, "chars", |value: String| value.chars().map(String::from).collect::<Vec<String>>());
+        builtins.register(Capability::Pure, "repeat", |value: String, count: usize| {
+            settle(reserve_string("repeat", value.len().checked_mul(count)).map(|_| value.repeat(count)))
+        });
+        builtins.register(Capability::Pure, "pad_left", |value: String, width: usize, fill: String| {
+            settle(padding("pad_left", &value, width, &fill).map(|padding| padding + &value))
+        });
+        builtins.register(Capability::Pure, "pad_right", |value: String, width: usize, fill: String| {
+            settle(padding("pad_right", &value, width, &fill).map(|padding| value.clone() + &padding))
         });
 
-        let push = Rc::new(Builtin{
-            func: Box::new(|args| {
-                let mut s = args[0].clone();
-                if args.len() != 2 {
-                    return Object::Null;
-                }
-                let element = args[1].clone();
-                if let Object::Array{elements} = &mut s {
-                    elements.push(element);
+        // Callbacks are called with each element; an error raised in one
+        // stops the builtin and is passed on as is.
+        builtins.register(Capability::Pure, "map", |elements: Vec<Object>, function: Callable| {
+            settle(elements.into_iter().map(|element| invoke(&function, vec![element])).collect::<Result<Vec<_>, _>>())
+        });
+        builtins.register(Capability::Pure, "filter", |elements: Vec<Object>, function: Callable| settle(filter(elements, &function)));
+        builtins.register(Capability::Pure, "reduce", |elements: Vec<Object>, initial: Object, function: Callable| {
+            settle(elements.into_iter().try_fold(initial, |total, element| invoke(&function, vec![total, element])))
+        });
+        builtins.register(Capability::Pure, "each", |elements: Vec<Object>, function: Callable| {
+            settle(elements.into_iter().try_for_each(|element| invoke(&function, vec![element]).map(drop)))
+        });
+        builtins.register(Capability::Pure, "find", |elements: Vec<Object>, function: Callable| settle(find(elements, &function)));
+        builtins.register(Capability::Pure, "any", |elements: Vec<Object>, function: Callable| {
+            settle(find(elements, &function).map(|found| found.is_some()))
+        });
+        builtins.register(Capability::Pure, "all", |elements: Vec<Object>, function: Callable| settle(all(elements, &function)));
+        builtins.register(Capability::Pure, "sort", |elements: Vec<Object>| {
+            let keys = elements.clone();
+            settle(sort_by_keys(elements, keys))
+        });
+        builtins.register(Capability::Pure, "sort_by", |elements: Vec<Object>, function: Callable| {
+            let keys: Result<Vec<_>, _> = elements.iter().map(|element| invoke(&function, vec![element.clone()])).collect();
+            settle(keys.and_then(|keys| sort_by_keys(elements, keys)))
+        });
+        builtins.register(Capability::Pure, "reverse", |mut elements: Vec<Object>| {
+            elements.reverse();
+            elements
+        });
+        builtins.register(Capability::Pure, "zip", |left: Vec<Object>, right: Vec<Object>| {
+            left.into_iter().zip(right).map(|(left, right)| vec![left, right]).collect::<Vec<_>>()
+        });
+        builtins.register(Capability::Pure, "flatten", |elements: Vec<Object>| {
+            let mut flat = Vec::new();
+            for mut element in elements {
+                match &mut element {
+                    Object::Array{elements} => flat.append(elements),
+                    _ => flat.push(element)
                 }
-                s
-            })
+            }
+            flat
+        });
+        builtins.register(Capability::Pure, "range", |start: i64, end: i64| {
+            let count = end.saturating_sub(start).max(0) as usize;
+            settle(limits::reserve_elements(count).map(|_| (start..end).collect::<Vec<i64>>()))
+        });
+        builtins.register(Capability::Pure, "slice", |value: Object, start: Object, end: Object| {
+            limits::track(eval_slice_expression(value, start, end))
+        });
+        builtins.register(Capability::Pure, "concat", |mut left: Vec<Object>, right: Vec<Object>| {
+            left.extend(right);
+            left
+        });
+        builtins.register(Capability::Pure, "unique", |elements: Vec<Object>| {
+            if let Some(element) = elements.iter().find(|element| !element.is_hashable()) {
+                return Err(RuntimeError(format!("argument 1 to `unique`: expected hashable elements, got {}", element.type_name())));
+            }
+            let mut seen = HashSet::new();
+            Ok(elements.into_iter().filter(|element| seen.insert(element.clone())).collect::<Vec<_>>())
         });
 
-        let puts = Rc::new(Builtin{
+        builtins.register(Capability::Pure, "Ok", |value: Object| Object::Ok{value: Box::new(value)});
+        builtins.register(Capability::Pure, "Err", |value: Object| Object::Err{value: Box::new(value)});
+        builtins.register(Capability::Pure, "Some", |value: Object| Object::Some{value: Box::new(value)});
+        builtins.builtins.set("None".to_string(), Object::None);
+
+        builtins.register(Capability::FsRead, "read_file", |path: String| {
+            fs::read_to_string(&path).map_err(|err| RuntimeError(format!("{}: {}", path, err)))
+        });
+        builtins.register(Capability::FsWrite, "write_file", |path: String, contents: String| {
+            fs::write(&path, contents).map_err(|err| RuntimeError(format!("{}: {}", path, err)))
+        });
+        builtins.register(Capability::Env, "getenv", |name: String| env::var(name).ok());
+        builtins.register(Capability::Clock, "now", || {
+            SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_millis() as i64).unwrap_or(0)
+        });
+        builtins.register(Capability::Process, "args", || env::args().skip(1).collect::<Vec<String>>());
+        builtins.install(Capability::Io, "puts", Rc::new(Builtin{
             func: Box::new(|args| {
                 println!("{:#?}", args);
                 Object::Null
             })
+        }));
+        // Exiting unwinds the script instead of ending the host's process;
+        // the interpreter reports it as `Error::Exit`.
+        builtins.register(Capability::Process, "exit", |code: i32| Object::new_error_of(EXIT_ERROR, code.to_string()));
+        builtins
+    }
+
+    pub fn capabilities(&self) -> &[Capability] {
+        &self.capabilities
+    }
+
+    pub fn allows(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+
+    /// Installs a typed Rust closure as a builtin. Arguments are converted
+    /// with `FromObject` and the result with `IntoObject`; calls with the
+    /// wrong number or types of arguments produce a runtime error.
+    ///
+    /// ```
+    /// use monkey::builtins::Builtins;
+    ///
+    /// let mut builtins = Builtins::new();
+    /// builtins.register_fn("repeat", |s: String, n: usize| s.repeat(n));
+    /// ```
+    pub fn register_fn<Args, F>(&mut self, name: &str, function: F)
+        where F: NativeFunction<Args> + 'static {
+        let builtin_name = name.to_string();
+        let builtin = Rc::new(Builtin{
+            func: Box::new(move |args| function.call(&builtin_name, args))
         });
-    
-        builtins.set("len".to_string(), Object::Builtin(len));
-        builtins.set("first".to_string(), Object::Builtin(first));
-        builtins.set("last".to_string(), Object::Builtin(last));
-        builtins.set("rest".to_string(), Object::Builtin(rest));
-        builtins.set("push".to_string(), Object::Builtin(push));
-        builtins.set("puts".to_string(), Object::Builtin(puts));
-    
-        Builtins {
-            builtins
+        self.builtins.set(name.to_string(), Object::Builtin(builtin));
+    }
+
+    /// Installs a builtin that belongs to `capability`. Without that
+    /// capability, calling it is a permission error instead.
+    pub fn register<Args, F>(&mut self, capability: Capability, name: &str, function: F)
+        where F: NativeFunction<Args> + 'static {
+        let builtin_name = name.to_string();
+        self.install(capability, name, Rc::new(Builtin{
+            func: Box::new(move |args| function.call(&builtin_name, args))
+        }));
+    }
+
+    fn install(&mut self, capability: Capability, name: &str, builtin: Rc<Builtin>) {
+        if self.allows(capability) {
+            return self.builtins.set(name.to_string(), Object::Builtin(builtin));
         }
+        let message = format!("permission denied: `{}` needs the {} capability", name, capability.name());
+        let denied = Rc::new(Builtin{
+            func: Box::new(move |_| Object::new_error_of(PERMISSION_ERROR, message.clone()))
+        });
+        self.builtins.set(name.to_string(), Object::Builtin(denied));
+    }
+}
+
+/// The `fill` characters that pad `value` to `width` characters.
+fn padding(name: &str, value: &str, width: usize, fill: &str) -> Result<String, Object> {
+    let mut chars = fill.chars();
+    let fill = match (chars.next(), chars.next()) {
+        (Some(fill), None) => fill,
+        _ => return Err(Object::new_error(format!("argument 3 to `{}`: expected a single character, got {:?}", name, fill)))
+    };
+    let count = width.saturating_sub(value.chars().count());
+    let bytes = count.checked_mul(fill.len_utf8()).and_then(|bytes| bytes.checked_add(value.len()));
+    reserve_string(name, bytes)?;
+    Ok(fill.to_string().repeat(count))
+}
+
+/// Checks the allocation budget before building a string of `bytes`, which is
+/// `None` when the size overflowed.
+fn reserve_string(name: &str, bytes: Option<usize>) -> Result<(), Object> {
+    match bytes.filter(|bytes| *bytes <= isize::MAX as usize) {
+        Some(bytes) => limits::reserve(bytes),
+        None => Err(Object::new_error(format!("result of `{}` is too large", name)))
+    }
+}
+
+/// Calls a callback of a builtin, with its error as the `Err`.
+fn invoke(function: &Callable, args: Vec<Object>) -> Result<Object, Object> {
+    match apply_function(function.object().clone(), args) {
+        error @ Object::Error{..} => Err(error),
+        result => Ok(result)
+    }
+}
+
+/// The result of a builtin whose callback may have raised an error.
+fn settle<T: IntoObject>(result: Result<T, Object>) -> Object {
+    match result {
+        Ok(value) => value.into_object(),
+        Err(error) => error
+    }
+}
+
+fn filter(elements: Vec<Object>, function: &Callable) -> Result<Vec<Object>, Object> {
+    let mut kept = Vec::new();
+    for element in elements {
+        if invoke(function, vec![element.clone()])?.is_truthy() {
+            kept.push(element);
+        }
+    }
+    Ok(kept)
+}
+
+fn find(elements: Vec<Object>, function: &Callable) -> Result<Option<Object>, Object> {
+    for element in elements {
+        if invoke(function, vec![element.clone()])?.is_truthy() {
+            return Ok(Some(element));
+        }
+    }
+    Ok(None)
+}
+
+fn all(elements: Vec<Object>, function: &Callable) -> Result<bool, Object> {
+    for element in elements {
+        if !invoke(function, vec![element])?.is_truthy() {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
 
+/// Stable sort of `elements` by `keys`, which must be all integers or all
+/// strings. The keys are checked up front, so comparing them cannot fail.
+fn sort_by_keys(elements: Vec<Object>, keys: Vec<Object>) -> Result<Vec<Object>, Object> {
+    let integers: Option<Vec<i64>> = keys.iter().map(|key| match key {
+        Object::Integer{value} => Some(*value),
+        _ => None
+    }).collect();
+    if let Some(integers) = integers {
+        return Ok(sorted(elements, integers));
     }
+    let strings: Option<Vec<&str>> = keys.iter().map(|key| match key {
+        Object::String{value} => Some(value.as_str()),
+        _ => None
+    }).collect();
+    if let Some(strings) = strings {
+        return Ok(sorted(elements, strings));
+    }
+    let first = &keys[0];
+    let other = keys.iter().find(|key| key.type_name() != first.type_name()).unwrap_or(first);
+    Err(Object::new_error(format!("cannot compare {} with {}: sort keys must be all INTEGERs or all STRINGs",
+        first.type_name(), other.type_name())))
+}
+
+fn sorted<K: Ord>(elements: Vec<Object>, keys: Vec<K>) -> Vec<Object> {
+    let mut pairs: Vec<(K, Object)> = keys.into_iter().zip(elements).collect();
+    pairs.sort_by(|(left, _), (right, _)| left.cmp(right));
+    pairs.into_iter().map(|(_, element)| element).collect()
 }
 
+#[cfg(test)]
+mod tests {
+    use super::{Builtins, Capability};
+    use crate::interpreter::{Error, Interpreter};
+
+    fn eval(source: &str) -> Result<String, Error> {
+        Interpreter::new().eval_str(source).map(|result| result.to_string())
+    }
+
+    #[test]
+    fn string_builtins() {
+        let cases = [
+            (r#"split("a,b,,c", ",")"#, r#"["a", "b", "", "c"]"#),
+            (r#"split("", ",")"#, r#"[""]"#),
+            (r#"split("a→b", "→")"#, r#"["a", "b"]"#),
+            (r#"join([1, "a", true], "-")"#, "1-a-true"),
+            (r#"join([], ",")"#, ""),
+            (r#"join(["a", "b"], "")"#, "ab"),
+            (r#"trim("  a b  ")"#, "a b"),
+            (r#"upper("straße")"#, "STRASSE"),
+            (r#"lower("ÄB")"#, "äb"),
+            (r#"replace("aaa", "a", "bb")"#, "bbbbbb"),
+            (r#"replace("héllo", "é", "e")"#, "hello"),
+            (r#"contains("héllo", "él")"#, "true"),
+            (r#"contains("abc", "")"#, "true"),
+            (r#"starts_with("héllo", "hé")"#, "true"),
+            (r#"starts_with("abc", "b")"#, "false"),
+            (r#"ends_with("abc", "bc")"#, "true"),
+            (r#"index_of("日本語", "語")"#, "Some(2)"),
+            (r#"index_of("abc", "z")"#, "None"),
+            (r#"substr("héllo", 1, 3)"#, "éll"),
+            (r#"substr("abc", 2, 10)"#, "c"),
+            (r#"chars("hé")"#, r#"["h", "é"]"#),
+            (r#"repeat("ab", 0)"#, ""),
+            (r#"pad_left("7", 3, "0")"#, "007"),
+            (r#"pad_right("é", 3, "·")"#, "é··"),
+            (r#"len("héllo")"#, "5"),
+            (r#"len("日本")"#, "2"),
+            (r#"len("")"#, "0")
+        ];
+        for (source, expected) in cases {
+            assert_eq!(eval(source), Ok(expected.to_string()), "{}", source);
+        }
+        for (source, message) in [
+            (r#"split("abc", "")"#, "separator of `split` must not be empty"),
+            (r#"replace("abc", "", "x")"#, "pattern of `replace` must not be empty"),
+            (r#"pad_left("x", 3, "ab")"#, r#"argument 3 to `pad_left`: expected a single character, got "ab""#),
+            (r#"pad_left("x", 3, "")"#, r#"argument 3 to `pad_left`: expected a single character, got """#)
+        ] {
+            assert_eq!(eval(source), Err(Error::Runtime(message.to_string())), "{}", source);
+        }
+    }
+
+    #[test]
+    fn strings_index_and_slice_by_character() {
+        let cases = [
+            (r#""日本語"[1]"#, "本"),
+            (r#""héllo"[4]"#, "o"),
+            (r#""abc"[-1]"#, "null"),
+            (r#""abc"[3]"#, "null"),
+            (r#""héllo"[1:3]"#, "él"),
+            (r#""héllo"[-3:]"#, "llo"),
+            (r#""abc"[:-1]"#, "ab"),
+            (r#""abc"[-10:]"#, "abc"),
+            (r#""abc"[5:10]"#, ""),
+            (r#""abc"[2:1]"#, ""),
+            (r#"slice("日本語", 1, -1)"#, "本"),
+            (r#"[1, 2, 3][-2:]"#, "[2, 3]"),
+            (r#"[1, 2, 3][4:]"#, "[]")
+        ];
+        for (source, expected) in cases {
+            assert_eq!(eval(source), Ok(expected.to_string()), "{}", source);
+        }
+        assert_eq!(eval(r#""abc"["a":]"#), Err(Error::Runtime("slice bounds must be INTEGER, got STRING".to_string())));
+    }
+
+    #[test]
+    fn reports_bad_builtin_arguments() {
+        let cases = [
+            ("len()", "wrong number of arguments: want=1, got=0"),
+            (r#"len("a", "b")"#, "wrong number of arguments: want=1, got=2"),
+            ("map([1])", "wrong number of arguments: want=2, got=1"),
+            ("len(1)", "argument 1 to `len`: expected STRING or ARRAY, got INTEGER"),
+            (r#"split(1, ",")"#, "argument 1 to `split`: expected STRING, got INTEGER"),
+            (r#"join(["a"], 1)"#, "argument 2 to `join`: expected STRING, got INTEGER"),
+            ("push(1, 2)", "argument 1 to `push`: expected ARRAY, got INTEGER"),
+            ("map([1], 2)", "argument 2 to `map`: expected FUNCTION, got INTEGER"),
+            ("unique([len])", "argument 1 to `unique`: expected hashable elements, got BUILTIN")
+        ];
+        for (source, message) in cases {
+            assert_eq!(eval(source), Err(Error::Runtime(message.to_string())), "{}", source);
+        }
+    }
 
+    #[test]
+    fn array_builtins() {
+        let cases = [
+            ("map([1, 2, 3], fn(x) { x * 2 })", "[2, 4, 6]"),
+            ("map([], fn(x) { x })", "[]"),
+            ("filter([1, 2, 3, 4], fn(x) { x > 2 })", "[3, 4]"),
+            ("filter([], fn(x) { true })", "[]"),
+            ("reduce([1, 2, 3], 10, fn(total, x) { total + x })", "16"),
+            ("reduce([], 10, fn(total, x) { total + x })", "10"),
+            ("sort([3, 1, 2])", "[1, 2, 3]"),
+            ("sort([\"b\", \"c\", \"a\"])", "[\"a\", \"b\", \"c\"]"),
+            ("sort([])", "[]"),
+            ("sort_by([\"ccc\", \"a\", \"bb\"], fn(s) { len(s) })", "[\"a\", \"bb\", \"ccc\"]"),
+            ("sort_by([[2, \"x\"], [1, \"y\"], [2, \"z\"]], fn(p) { p[0] })", "[[1, \"y\"], [2, \"x\"], [2, \"z\"]]"),
+            ("sort_by([], fn(x) { x })", "[]"),
+            ("zip([1, 2, 3], [\"a\", \"b\"])", "[[1, \"a\"], [2, \"b\"]]"),
+            ("zip([], [1])", "[]"),
+            ("flatten([[1, 2], 3, [], [[4]]])", "[1, 2, 3, [4]]"),
+            ("flatten([])", "[]"),
+            ("unique([1, 2, 1, 3, 2])", "[1, 2, 3]"),
+            ("unique([])", "[]"),
+            ("find([1, 2, 3], fn(x) { x > 1 })", "Some(2)"),
+            ("find([], fn(x) { true })", "None"),
+            ("find([1, 2, 3], fn(x) { x > 1 }) == Some(2)", "true"),
+            ("index_of(\"hello\", \"l\")", "Some(2)"),
+            ("index_of(\"hello\", \"z\") == None", "true")
+        ];
+        for (source, expected) in cases {
+            assert_eq!(eval(source), Ok(expected.to_string()), "{}", source);
+        }
+    }
+
+    #[test]
+    fn array_builtins_pass_on_callback_errors() {
+        let boom = Err(Error::Runtime("boom".to_string()));
+        for source in [
+            "map([1, 2], fn(x) { throw \"boom\" })",
+            "filter([1, 2], fn(x) { throw \"boom\" })",
+            "reduce([1, 2], 0, fn(total, x) { throw \"boom\" })",
+            "sort_by([1, 2], fn(x) { throw \"boom\" })",
+            "find([1, 2], fn(x) { throw \"boom\" })"
+        ] {
+            assert_eq!(eval(source), boom, "{}", source);
+        }
+        assert_eq!(eval("try { map([1], fn(x) { throw \"boom\" }) } catch (e) { e[\"message\"] }"), Ok("boom".to_string()));
+        assert_eq!(eval("map([], fn(x) { throw \"boom\" })"), Ok("[]".to_string()));
+    }
+
+    #[test]
+    fn sort_rejects_mixed_keys() {
+        let mixed = |left: &str, right: &str| Err(Error::Runtime(format!(
+            "cannot compare {} with {}: sort keys must be all INTEGERs or all STRINGs", left, right)));
+        assert_eq!(eval("sort([1, \"a\", 2])"), mixed("INTEGER", "STRING"));
+        assert_eq!(eval("sort([true, false])"), mixed("BOOLEAN", "BOOLEAN"));
+        assert_eq!(eval("sort_by([1, 2], fn(x) { if (x == 1) { \"a\" } else { x } })"), mixed("STRING", "INTEGER"));
+    }
+
+    #[test]
+    fn recursion_through_callbacks_hits_a_limit() {
+        let exceeded = Err(Error::Runtime("limit exceeded: callbacks nested more than 1000 deep".to_string()));
+        assert_eq!(eval("let f = fn(n) { map([n], f) }; f(0)"), exceeded);
+        assert_eq!(eval("let f = fn(n) { try { map([n], f) } catch (e) { 0 } }; f(0)"), exceeded);
+        assert_eq!(eval("let f = fn(n) { if (n == 0) { 0 } else { reduce([n], 0, fn(a, x) { f(x - 1) }) } }; f(900)"),
+            Ok("0".to_string()));
+    }
+
+    #[test]
+    fn io_failures_are_runtime_errors() {
+        let mut interpreter = Interpreter::with_builtins(Builtins::with_capabilities(&[Capability::Pure, Capability::FsRead]));
+        let err = interpreter.eval_str("read_file(\"/nonexistent/monkey\")").unwrap_err();
+        assert!(err.to_string().starts_with("runtime error: /nonexistent/monkey: "), "{}", err);
+        assert_eq!(interpreter.eval_str("try { read_file(\"/nonexistent/monkey\"); 1 } catch (e) { 2 }").map(|r| r.to_string()),
+            Ok("2".to_string()));
+    }
+
+    #[test]
+    fn denied_builtins_are_permission_errors() {
+        let denied = |name: &str, capability: &str| Err(Error::Runtime(format!("permission denied: `{}` needs the {} capability",
+            name, capability)));
+        let mut sandbox = Interpreter::with_builtins(Builtins::with_capabilities(&[]));
+        assert_eq!(sandbox.eval_str("len(\"abc\")"), denied("len", "pure"));
+        assert_eq!(sandbox.eval_str("map([1], fn(x) { x })"), denied("map", "pure"));
+        let mut pure = Interpreter::new();
+        assert_eq!(pure.eval_str("puts(1)"), denied("puts", "io"));
+        assert_eq!(pure.eval_str("exit(0)"), denied("exit", "process"));
+        assert_eq!(pure.eval_str("getenv(\"HOME\")"), denied("getenv", "env"));
+        assert_eq!(pure.eval_str("try { now() } catch (e) { e[\"kind\"] }").map(|r| r.to_string()), Ok("permission".to_string()));
+    }
+
+    #[test]
+    fn exit_stops_the_script_but_not_the_host() {
+        let mut interpreter = Interpreter::with_builtins(Builtins::with_capabilities(&[Capability::Pure, Capability::Process]));
+        assert_eq!(interpreter.eval_str("exit(3); 1"), Err(Error::Exit(3)));
+        assert_eq!(interpreter.eval_str("try { exit(4) } catch (e) { 0 } finally { 1 }"), Err(Error::Exit(4)));
+        assert_eq!(interpreter.eval_str("map([5], fn(code) { exit(code) })"), Err(Error::Exit(5)));
+        interpreter.eval_str("let quit = fn() { exit(6) };").unwrap();
+        assert_eq!(interpreter.call_function("quit", ()), Err(Error::Exit(6)));
+    }
+}
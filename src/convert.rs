@@ -0,0 +1,477 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt::Display;
+use std::hash::Hash;
+
+use crate::evaluator::apply_function;
+use crate::limits::{self, Limits};
+use crate::object::Object;
+
+/// Conversion from a Monkey value into a Rust value. The error is a
+/// message such as "expected INTEGER, got BOOLEAN".
+pub trait FromObject: Sized {
+    fn from_object(object: Object) -> Result<Self, String>;
+}
+
+/// Conversion from a Rust value into a Monkey value.
+pub trait IntoObject {
+    fn into_object(self) -> Object;
+}
+
+fn mismatch<T>(expected: &str, object: &Object) -> Result<T, String> {
+    Err(format!("expected {}, got {}", expected, object.type_name()))
+}
+
+impl FromObject for Object {
+    fn from_object(object: Object) -> Result<Self, String> {
+        Ok(object)
+    }
+}
+
+impl IntoObject for Object {
+    fn into_object(self) -> Object {
+        self
+    }
+}
+
+macro_rules! impl_integer {
+    ($($ty:ty),*) => {
+        $(
+            impl FromObject for $ty {
+                fn from_object(object: Object) -> Result<Self, String> {
+                    match object {
+                        Object::Integer{value} => <$ty>::try_from(value)
+                            .map_err(|_| format!("integer {} out of range for {}", value, stringify!($ty))),
+                        other => mismatch("INTEGER", &other)
+                    }
+                }
+            }
+
+            impl IntoObject for $ty {
+                fn into_object(self) -> Object {
+                    match i64::try_from(self) {
+                        Ok(value) => Object::new_int(value),
+                        Err(_) => Object::new_error(format!("integer {} out of range", self))
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_integer!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl FromObject for bool {
+    fn from_object(object: Object) -> Result<Self, String> {
+        match object {
+            Object::Boolean{value} => Ok(value),
+            other => mismatch("BOOLEAN", &other)
+        }
+    }
+}
+
+impl IntoObject for bool {
+    fn into_object(self) -> Object {
+        Object::new_bool(self)
+    }
+}
+
+impl FromObject for String {
+    fn from_object(mut object: Object) -> Result<Self, String> {
+        match &mut object {
+            Object::String{value} => Ok(std::mem::take(value)),
+            other => mismatch("STRING", other)
+        }
+    }
+}
+
+impl IntoObject for String {
+    fn into_object(self) -> Object {
+        limits::track(Object::new_string(self))
+    }
+}
+
+impl IntoObject for &str {
+    fn into_object(self) -> Object {
+        limits::track(Object::new_string(self.to_string()))
+    }
+}
+
+impl IntoObject for () {
+    fn into_object(self) -> Object {
+        Object::Null
+    }
+}
+
+impl<T: FromObject> FromObject for Vec<T> {
+    fn from_object(mut object: Object) -> Result<Self, String> {
+        match &mut object {
+            Object::Array{elements} => std::mem::take(elements).into_iter().map(T::from_object).collect(),
+            other => mismatch("ARRAY", other)
+        }
+    }
+}
+
+impl<T: IntoObject> IntoObject for Vec<T> {
+    fn into_object(self) -> Object {
+        match self.into_iter().map(convert).collect() {
+            Ok(elements) => limits::track(Object::Array{elements}),
+            Err(error) => error
+        }
+    }
+}
+
+impl<K: FromObject + Eq + Hash, V: FromObject> FromObject for HashMap<K, V> {
+    fn from_object(mut object: Object) -> Result<Self, String> {
+        match &mut object {
+            Object::HashM{pairs} => std::mem::take(pairs).into_iter()
+                .map(|(key, value)| Ok((K::from_object(key)?, V::from_object(value)?)))
+                .collect(),
+            other => mismatch("HASH", other)
+        }
+    }
+}
+
+impl<K: IntoObject, V: IntoObject> IntoObject for HashMap<K, V> {
+    fn into_object(self) -> Object {
+        match self.into_iter().map(|(key, value)| Ok((convert(key)?, convert(value)?))).collect() {
+            Ok(pairs) => limits::track(Object::HashM{pairs}),
+            Err(error) => error
+        }
+    }
+}
+
+/// `None` and `null` are `None`, `Some(value)` or any other value must
+/// convert to `T`.
+impl<T: FromObject> FromObject for Option<T> {
+    fn from_object(object: Object) -> Result<Self, String> {
+        match object {
+            Object::None | Object::Null => Ok(None),
+            mut object @ Object::Some{..} => T::from_object(object.take_inner().unwrap_or_default()).map(Some),
+            other => T::from_object(other).map(Some)
+        }
+    }
+}
+
+impl<T: IntoObject> IntoObject for Option<T> {
+    fn into_object(self) -> Object {
+        match self {
+            Some(value) => wrap(value, |value| Object::Some{value}),
+            None => Object::None
+        }
+    }
+}
+
+/// `Ok(value)` and `Err(error)`. A Monkey runtime error is `Err(message)`.
+impl<T: FromObject, E: FromObject> FromObject for Result<T, E> {
+    fn from_object(mut object: Object) -> Result<Self, String> {
+        match &mut object {
+            Object::Ok{..} => T::from_object(object.take_inner().unwrap_or_default()).map(Ok),
+            Object::Err{..} => E::from_object(object.take_inner().unwrap_or_default()).map(Err),
+            Object::Error{message, ..} => E::from_object(Object::new_string(std::mem::take(message))).map(Err),
+            other => mismatch("OK or ERR", other)
+        }
+    }
+}
+
+impl<T: IntoObject, E: IntoObject> IntoObject for Result<T, E> {
+    fn into_object(self) -> Object {
+        match self {
+            Ok(value) => wrap(value, |value| Object::Ok{value}),
+            Err(err) => wrap(err, |value| Object::Err{value})
+        }
+    }
+}
+
+/// Converts an element of a container, failing with the error it
+/// converted to, if any.
+fn convert<T: IntoObject>(value: T) -> Result<Object, Object> {
+    match value.into_object() {
+        error @ Object::Error{..} => Err(error),
+        value => Ok(value)
+    }
+}
+
+/// Converts `value` and wraps it in a variant, unless the conversion
+/// failed.
+fn wrap<T: IntoObject>(value: T, variant: fn(Box<Object>) -> Object) -> Object {
+    match value.into_object() {
+        error @ Object::Error{..} => error,
+        value => variant(Box::new(value))
+    }
+}
+
+/// An error a host function raises as a Monkey runtime error, by returning
+/// `Result<T, RuntimeError>`. `?` turns a `String` error into one.
+///
+/// ```
+/// use monkey::{builtins::Builtins, convert::RuntimeError, Interpreter};
+///
+/// let mut builtins = Builtins::new();
+/// builtins.register_fn("half", |n: i64| -> Result<i64, RuntimeError> {
+///     if n % 2 != 0 {
+///         return Err(format!("{} is odd", n).into());
+///     }
+///     Ok(n / 2)
+/// });
+/// let mut interpreter = Interpreter::with_builtins(builtins);
+/// assert_eq!(interpreter.eval_str("half(3)").unwrap_err().to_string(), "runtime error: 3 is odd");
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError(pub String);
+
+impl From<String> for RuntimeError {
+    fn from(message: String) -> Self {
+        RuntimeError(message)
+    }
+}
+
+impl From<&str> for RuntimeError {
+    fn from(message: &str) -> Self {
+        RuntimeError(message.to_string())
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// `Err` becomes a Monkey runtime error carrying the error's message.
+impl<T: IntoObject> IntoObject for Result<T, RuntimeError> {
+    fn into_object(self) -> Object {
+        match self {
+            Ok(value) => value.into_object(),
+            Err(RuntimeError(message)) => Object::new_error(message)
+        }
+    }
+}
+
+/// Arguments for calling a Monkey function from Rust: a `Vec<Object>` or a
+/// tuple of up to six `IntoObject` values.
+pub trait IntoArgs {
+    fn into_args(self) -> Vec<Object>;
+}
+
+impl IntoArgs for Vec<Object> {
+    fn into_args(self) -> Vec<Object> {
+        self
+    }
+}
+
+macro_rules! impl_into_args {
+    ($($arg:ident),*) => {
+        impl<$($arg: IntoObject),*> IntoArgs for ($($arg,)*) {
+            #[allow(non_snake_case)]
+            fn into_args(self) -> Vec<Object> {
+                let ($($arg,)*) = self;
+                vec![$($arg.into_object()),*]
+            }
+        }
+    };
+}
+
+impl_into_args!();
+impl_into_args!(A);
+impl_into_args!(A, B);
+impl_into_args!(A, B, C);
+impl_into_args!(A, B, C, D);
+impl_into_args!(A, B, C, D, E);
+impl_into_args!(A, B, C, D, E, F);
+
+/// A Monkey function or builtin held on the Rust side, e.g. a callback
+/// passed to a registered builtin or kept by the host to call later.
+///
+/// A callable runs under the limits that were in force when it was
+/// created, so a script can't hand the host a callback that escapes them.
+/// `Callable::from_object` only works during an evaluation; outside of one,
+/// get a callable from `Interpreter::callable` to run it under that
+/// interpreter's limits.
+///
+/// ```
+/// use monkey::{builtins::Builtins, convert::{Callable, RuntimeError}, Interpreter};
+///
+/// let mut builtins = Builtins::new();
+/// builtins.register_fn("twice", |f: Callable, x: i64| -> Result<i64, RuntimeError> {
+///     let once: i64 = f.call_as((x,))?;
+///     Ok(f.call_as((once,))?)
+/// });
+/// let mut interpreter = Interpreter::with_builtins(builtins);
+/// let result = interpreter.eval_str("twice(fn(x) { x * 3 }, 2)").unwrap();
+/// assert_eq!(result, monkey::object::Object::new_int(18));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Callable {
+    function: Object,
+    limits: Limits
+}
+
+impl Callable {
+    pub(crate) fn with_limits(function: Object, limits: Limits) -> Result<Self, String> {
+        match function {
+            Object::Function{..} | Object::Builtin(_) => Ok(Callable{function, limits}),
+            other => mismatch("FUNCTION", &other)
+        }
+    }
+
+    /// Calls the function; a Monkey runtime error becomes `Err(message)`.
+    /// Called while no evaluation is under way, the function gets a fresh
+    /// budget of the limits the callable was created under.
+    pub fn call<A: IntoArgs>(&self, args: A) -> Result<Object, String> {
+        let mut result = self.call_object(args);
+        match &mut result {
+            Object::Error{message, ..} => Err(std::mem::take(message)),
+            _ => Ok(result)
+        }
+    }
+
+    /// `call`, with errors left as `Object::Error`.
+    pub(crate) fn call_object<A: IntoArgs>(&self, args: A) -> Object {
+        let _budget = match limits::current() {
+            Some(_) => None,
+            None => Some(limits::start(self.limits))
+        };
+        apply_function(self.function.clone(), args.into_args())
+    }
+
+    /// Calls the function and converts its result to `R`.
+    pub fn call_as<R: FromObject, A: IntoArgs>(&self, args: A) -> Result<R, String> {
+        R::from_object(self.call(args)?)
+    }
+
+    pub fn object(&self) -> &Object {
+        &self.function
+    }
+}
+
+impl FromObject for Callable {
+    fn from_object(object: Object) -> Result<Self, String> {
+        let limits = match limits::current() {
+            Some(limits) => limits,
+            None if matches!(object, Object::Function{..} | Object::Builtin(_)) => {
+                return Err("a function outside of an evaluation has no limits to run under: \
+                    use `Interpreter::callable`".to_string());
+            },
+            None => return mismatch("FUNCTION", &object)
+        };
+        Callable::with_limits(object, limits)
+    }
+}
+
+impl IntoObject for Callable {
+    fn into_object(self) -> Object {
+        self.function
+    }
+}
+
+/// A Rust closure callable from Monkey. Implemented for `Fn`s of up to six
+/// arguments whose parameters are `FromObject` and whose result is
+/// `IntoObject`; `Args` is the tuple of parameter types.
+pub trait NativeFunction<Args> {
+    fn call(&self, name: &str, args: Vec<Object>) -> Object;
+}
+
+macro_rules! impl_native_function {
+    ($($arg:ident),*) => {
+        impl<Func, Ret, $($arg),*> NativeFunction<($($arg,)*)> for Func
+            where Func: Fn($($arg),*) -> Ret, Ret: IntoObject, $($arg: FromObject),* {
+            #[allow(non_snake_case, unused_mut, unused_variables)]
+            fn call(&self, name: &str, args: Vec<Object>) -> Object {
+                let arity = <[&str]>::len(&[$(stringify!($arg)),*]);
+                if args.len() != arity {
+                    return Object::new_error(format!("wrong number of arguments: want={}, got={}",
+                        arity, args.len()));
+                }
+                let mut args = args.into_iter().enumerate();
+                $(
+                    let (idx, arg) = args.next().unwrap();
+                    let $arg = match $arg::from_object(arg) {
+                        Ok(value) => value,
+                        Err(err) => return Object::new_error(format!("argument {} to `{}`: {}", idx + 1, name, err))
+                    };
+                )*
+                (self)($($arg),*).into_object()
+            }
+        }
+    };
+}
+
+impl_native_function!();
+impl_native_function!(A);
+impl_native_function!(A, B);
+impl_native_function!(A, B, C);
+impl_native_function!(A, B, C, D);
+impl_native_function!(A, B, C, D, E);
+impl_native_function!(A, B, C, D, E, F);
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{FromObject, IntoObject};
+    use crate::builtins::Builtins;
+    use crate::interpreter::{Error, Interpreter};
+    use crate::object::Object;
+
+    fn round_trip<T: IntoObject + FromObject + Clone>(value: T) -> (String, Result<T, String>) {
+        let object = value.into_object();
+        (object.to_string(), T::from_object(object))
+    }
+
+    #[test]
+    fn options_and_results_are_monkey_variants() {
+        assert_eq!(round_trip(Some(1)), ("Some(1)".to_string(), Ok(Some(1))));
+        assert_eq!(round_trip(None::<i64>), ("None".to_string(), Ok(None)));
+        assert_eq!(round_trip(Ok::<i64, String>(1)), ("Ok(1)".to_string(), Ok(Ok(1))));
+        assert_eq!(round_trip(Err::<i64, String>("no".to_string())), ("Err(\"no\")".to_string(), Ok(Err("no".to_string()))));
+        assert_eq!(round_trip(vec![Some(Ok::<bool, i64>(true)), None]).0, "[Some(Ok(true)), None]");
+
+        assert_eq!(Option::<i64>::from_object(Object::Null), Ok(None));
+        assert_eq!(Option::<i64>::from_object(Object::new_int(2)), Ok(Some(2)));
+        assert_eq!(Result::<i64, String>::from_object(Object::new_error("boom".to_string())), Ok(Err("boom".to_string())));
+        assert_eq!(Result::<i64, String>::from_object(Object::new_int(2)), Err("expected OK or ERR, got INTEGER".to_string()));
+        assert_eq!(Some(u64::MAX).into_object(), Object::new_error(format!("integer {} out of range", u64::MAX)));
+    }
+
+    #[test]
+    fn reports_conversion_failures() {
+        assert_eq!(i64::from_object(Object::new_bool(true)), Err("expected INTEGER, got BOOLEAN".to_string()));
+        assert_eq!(u8::from_object(Object::new_int(300)), Err("integer 300 out of range for u8".to_string()));
+        assert_eq!(usize::from_object(Object::new_int(-1)), Err("integer -1 out of range for usize".to_string()));
+        assert_eq!(String::from_object(Object::Null), Err("expected STRING, got NULL".to_string()));
+        assert_eq!(Vec::<i64>::from_object(Object::Array{elements: vec![Object::new_int(1), Object::new_string("a".to_string())]}),
+            Err("expected INTEGER, got STRING".to_string()));
+        assert_eq!(HashMap::<String, i64>::from_object(Object::Array{elements: vec![]}), Err("expected HASH, got ARRAY".to_string()));
+        assert_eq!(u64::MAX.into_object(), Object::new_error(format!("integer {} out of range", u64::MAX)));
+        let too_big = Object::new_error(format!("integer {} out of range", u64::MAX));
+        assert_eq!(vec![1, u64::MAX, 2].into_object(), too_big);
+        assert_eq!(HashMap::from([("a", u64::MAX)]).into_object(), too_big);
+        assert_eq!(HashMap::from([(u64::MAX, "a")]).into_object(), too_big);
+    }
+
+    #[test]
+    fn register_fn_checks_arity_and_types() {
+        let mut builtins = Builtins::new();
+        builtins.register_fn("add", |a: i64, b: i64| a + b);
+        builtins.register_fn("byte", |b: u8| b);
+        builtins.register_fn("big", || u64::MAX);
+        builtins.register_fn("lengths", |words: HashMap<String, String>| {
+            words.into_iter().map(|(key, word)| (key, word.len())).collect::<HashMap<String, usize>>()
+        });
+        let mut interpreter = Interpreter::with_builtins(builtins);
+        let cases = [
+            ("add(1)", "wrong number of arguments: want=2, got=1"),
+            ("add(1, 2, 3)", "wrong number of arguments: want=2, got=3"),
+            ("add(1, true)", "argument 2 to `add`: expected INTEGER, got BOOLEAN"),
+            ("byte(300)", "argument 1 to `byte`: integer 300 out of range for u8"),
+            ("big()", "integer 18446744073709551615 out of range"),
+            ("lengths({\"a\": 1})", "argument 1 to `lengths`: expected STRING, got INTEGER")
+        ];
+        for (source, message) in cases {
+            assert_eq!(interpreter.eval_str(source), Err(Error::Runtime(message.to_string())), "{}", source);
+        }
+        assert_eq!(interpreter.eval_str("add(40, 2)"), Ok(Object::new_int(42)));
+        assert_eq!(interpreter.eval_str("lengths({\"a\": \"xyz\"})[\"a\"]"), Ok(Object::new_int(3)));
+    }
+}
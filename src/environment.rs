@@ -1,25 +1,65 @@
-use std::collections::HashMap;
+use std::{cell::RefCell, collections::HashMap, fmt, rc::Rc};
 
 use crate::object::Object;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// A scope frame. Globals (and anything the resolver has not addressed)
+/// live in `store` and are looked up by name; function parameters and
+/// locals resolved to a `(depth, slot)` pair live in `slots`.
+///
+/// Frames are shared, so closures see later updates to the scopes they
+/// captured, including their own binding when defined with `let`.
+#[derive(Clone, Default)]
 pub struct Environment {
     pub store: HashMap<String, Object>,
-    pub outer: Option<Box<Self>>
+    pub slots: Vec<Object>,
+    pub outer: Option<Rc<RefCell<Self>>>
+}
+
+impl fmt::Debug for Environment {
+    // Functions capture the frame they are stored in, so printing the
+    // values could recurse forever; names and slot count are enough.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names: Vec<&String> = self.store.keys().collect();
+        names.sort();
+        f.debug_struct("Environment")
+            .field("names", &names)
+            .field("slots", &self.slots.len())
+            .finish()
+    }
+}
+
+// A chain of frames can be as long as a run of tail calls, so frames
+// nobody else holds are unlinked in a loop rather than dropped in turn.
+impl Drop for Environment {
+    fn drop(&mut self) {
+        let mut next = self.outer.take();
+        while let Some(outer) = next {
+            next = match Rc::try_unwrap(outer) {
+                Ok(frame) => frame.into_inner().outer.take(),
+                Err(_) => None
+            };
+        }
+    }
 }
 
 impl Environment {
     pub fn new() -> Self {
         Environment {
             store: HashMap::new(),
+            slots: Vec::new(),
             outer: None
         }
     }
 
     pub fn create_child(self) -> Self {
+        Environment::new_enclosed(Rc::new(RefCell::new(self)))
+    }
+
+    pub fn new_enclosed(outer: Rc<RefCell<Self>>) -> Self {
         Environment {
             store: HashMap::new(),
-            outer: Some(Box::new(self))
+            slots: Vec::new(),
+            outer: Some(outer)
         }
     }
 
@@ -27,7 +67,40 @@ impl Environment {
         self.store.insert(name, value);
     }
 
-    pub fn get(&self, name: &String) -> Option<&Object> {
-        self.store.get(name).or_else(|| self.outer.as_ref().and_then(|outer| outer.get(name)))
+    // Lookups walk the chain of frames in a loop: it is as long as the
+    // nesting of the calls that are running.
+    pub fn get(&self, name: &str) -> Option<Object> {
+        if let Some(value) = self.store.get(name) {
+            return Some(value.clone());
+        }
+        let mut next = self.outer.clone();
+        while let Some(frame) = next {
+            let frame = frame.borrow();
+            if let Some(value) = frame.store.get(name) {
+                return Some(value.clone());
+            }
+            next = frame.outer.clone();
+        }
+        None
     }
-}
\ No newline at end of file
+
+    pub fn set_slot(&mut self, slot: usize, value: Object) {
+        if slot >= self.slots.len() {
+            self.slots.resize(slot + 1, Object::Null);
+        }
+        self.slots[slot] = value;
+    }
+
+    pub fn get_slot(&self, depth: usize, slot: usize) -> Option<Object> {
+        if depth == 0 {
+            return self.slots.get(slot).cloned();
+        }
+        let mut frame = self.outer.clone()?;
+        for _ in 1..depth {
+            let outer = frame.borrow().outer.clone()?;
+            frame = outer;
+        }
+        let value = frame.borrow().slots.get(slot).cloned();
+        value
+    }
+}
@@ -3,14 +3,16 @@
 use std::{cell::RefCell, collections::HashMap};
 use std::rc::Rc;
 
-use crate::{ast::{Expression, Program, Statement}, environment::Environment, object::{Object}};
+use typed_arena::Arena;
+
+use crate::{ast::{Catch, Expression, MatchArm, Pattern, Program, Statement}, environment::Environment, limits, object::{Object, EXIT_ERROR, LIMIT_ERROR, THROWN_ERROR}, visitor::with_stack};
 
 pub trait Evaluator {
     fn evaluate(&self, environment: Rc<RefCell<Environment>>) -> Object;
 }
 
-fn evaluate_prefix_expression(operator: &String, right: Object) -> Object {
-    match operator.as_str() {
+fn evaluate_prefix_expression(operator: &str, right: Object) -> Object {
+    match operator {
         "!" => evaluate_bang_operator_expression(right),
         "-" => evaluate_minus_prefix_operator_expression(right),
         _ => Object::Null
@@ -19,134 +21,337 @@ fn evaluate_prefix_expression(operator: &String, right: Object) -> Object {
 
 fn evaluate_minus_prefix_operator_expression(right: Object) -> Object {
     if let Object::Integer{value} = right {
-        return Object::new_int(-value)
+        return match value.checked_neg() {
+            Some(value) => Object::new_int(value),
+            None => Object::new_error(format!("integer overflow: -({})", value))
+        };
     }
     Object::Null
 }
 
 fn evaluate_bang_operator_expression(right: Object) -> Object {
-    match right {
-        Object::Boolean{
-            value
-        } => {
-            Object::new_bool(!value)
-        },
-        Object::Null => Object::new_bool(false),
-        _ => Object::new_bool(false)
-    }
+    Object::new_bool(!right.is_truthy())
 }
 
-fn evaluate_infix_expression(operator: &String, left: Object, right: Object) -> Object{
+fn evaluate_infix_expression(operator: &str, left: Object, right: Object) -> Object{
     if let  Object::Integer{value: left} = left {
         if let  Object::Integer{value: right} = right {
-            match operator.as_str() {
-                "+" => return Object::new_int(left+right),
-                "-" => return Object::new_int(left-right),
-                "*" => return Object::new_int(left*right),
-                "/" => return Object::new_int(left/right),
-                "<" => return Object::new_bool(left < right),
-                ">" => return Object::new_bool(left > right),
-                "<=" => return Object::new_bool(left <= right),
-                ">=" => return Object::new_bool(left >= right),
-                "==" => return Object::new_bool(left == right),
-                "!=" => return Object::new_bool(left != right),
-               _=> return Object::Null
-            } 
+            let result = match operator {
+                "+" => left.checked_add(right),
+                "-" => left.checked_sub(right),
+                "*" => left.checked_mul(right),
+                "/" if right == 0 => return Object::new_error("division by zero".to_string()),
+                "/" => left.checked_div(right),
+                _ => return compare_integers(operator, left, right)
+            };
+            return match result {
+                Some(value) => Object::new_int(value),
+                None => Object::new_error(format!("integer overflow: {} {} {}", left, operator, right))
+            };
         }
     } else if let  Object::Boolean{value: left} = left {
         if let  Object::Boolean{value: right} = right {
-            match operator.as_str() {
+            match operator {
                 "==" => return Object::new_bool(left == right),
                 "!=" => return Object::new_bool(left != right),
                _=> return Object::Null
             } 
         }
-    } else if let  Object::String{value: left} = left {
-        if let  Object::String{value: right} = right {
-            match operator.as_str() {
+    } else if let  Object::String{value: left} = &left {
+        if let  Object::String{value: right} = &right {
+            match operator {
                 "+" => {
+                    if let Err(error) = limits::reserve(left.len() + right.len()) {
+                        return error;
+                    }
                     let mut s = left.to_owned();
                     s.push_str(right.as_str());
-                    return Object::new_string(s)
+                    return limits::track(Object::new_string(s))
                 }
 
                _=> return Object::Null
             } 
         }
+    } else if let Object::Native(left) = &left {
+        if let Object::Native(right) = &right {
+            match operator {
+                "==" => return Object::new_bool(left == right),
+                "!=" => return Object::new_bool(left != right),
+               _=> return Object::Null
+            }
+        }
+    }
+    // `Ok`, `Err`, `Some` and `None` compare by value, so `x == None` works.
+    let variant = |object: &Object| matches!(object, Object::Ok{..} | Object::Err{..} | Object::Some{..} | Object::None);
+    if variant(&left) || variant(&right) {
+        match operator {
+            "==" => return Object::new_bool(left == right),
+            "!=" => return Object::new_bool(left != right),
+            _ => {}
+        }
     }
 
-    return Object::Null;
-    
+    Object::Null
 }
 
-fn is_truthy(object: Object) -> bool {
-    match object {
-        Object::Boolean{value} => {
-            value
-        },
-        _ => false
+fn compare_integers(operator: &str, left: i64, right: i64) -> Object {
+    match operator {
+        "<" => Object::new_bool(left < right),
+        ">" => Object::new_bool(left > right),
+        "<=" => Object::new_bool(left <= right),
+        ">=" => Object::new_bool(left >= right),
+        "==" => Object::new_bool(left == right),
+        "!=" => Object::new_bool(left != right),
+        _ => Object::Null
     }
 }
 
-fn eval_if_expression(condition: &Box<Expression> ,
-     consequence: &Box<Statement>,
-      alternative: &Option<Box<Statement>>,
-      environment: Rc<RefCell<Environment>>) -> Object {
-    let condition = condition.evaluate(environment.clone());
-    if is_truthy(condition) {
-        return consequence.evaluate(environment.clone());
-    } else if alternative.is_some() {
-        return alternative.as_ref().unwrap().evaluate(environment);
+fn eval_identifier(name: &str, environment: Rc<RefCell<Environment>>) -> Object {
+    if let Some(value) = environment.borrow().get(name) {
+        return value;
     }
     Object::Null
 }
 
-fn eval_identifier(name: &String, environment: Rc<RefCell<Environment>>) -> Object {
-    if let Some(value) = environment.borrow_mut().get(&name) {
-        return value.clone();
+fn eval_local(depth: usize, slot: usize, environment: Rc<RefCell<Environment>>) -> Object {
+    if let Some(value) = environment.borrow().get_slot(depth, slot) {
+        return value;
     }
     Object::Null
-} 
-
-fn eval_expressions(arguments: &Vec<Expression>, environment: Rc<RefCell<Environment>>) -> Vec<Object> {
-    let mut expressions = vec!();
-    for arg in arguments {
-        let res = arg.evaluate(environment.clone());
-        expressions.push(res);
-    }
-    expressions
-}
-
-fn apply_function(function: Object, args: Vec<Object>) -> Object {
-    match function {
-        Object::Builtin(builtin) => {
-               (builtin.as_ref().func)(args)
-        }
-        Object::Function {
-            parameters,
-            body,
-            environment
-        } => {
-            let child = environment.clone().borrow().clone().create_child();
-            let environment = Rc::new(RefCell::new(child));
-
-            for (idx, param) in parameters.iter().enumerate() {
-                if let Expression::Identifier {
-                    token:_,
-                    value: name
-                } = param {
-                    (*environment.borrow_mut()).set(name.as_str().to_string(), args[idx].clone());
+}
+
+fn name_of(name: &Expression) -> &str {
+    match name {
+        Expression::Identifier{token:_, value} | Expression::Local{token:_, value, ..} => value,
+        _ => ""
+    }
+}
+
+/// The number of arguments `parameters` take, for arity errors.
+fn arity(parameters: &[Expression]) -> String {
+    let required = parameters.iter()
+        .filter(|parameter| !matches!(parameter, Expression::Named{..} | Expression::Spread{..}))
+        .count();
+    if let Some(Expression::Spread{..}) = parameters.last() {
+        format!("at least {}", required)
+    } else if required < parameters.len() {
+        format!("{} to {}", required, parameters.len())
+    } else {
+        required.to_string()
+    }
+}
+
+/// Binds the arguments of a call: positional arguments in order, then
+/// keyword arguments by name. A rest parameter takes the positional
+/// arguments left over. Returns the parameters left to their default
+/// values, for the caller to evaluate in the new scope.
+fn bind_parameters<'p>(parameters: &'p [Expression], args: Vec<Object>, mut keywords: Keywords,
+    environment: &Rc<RefCell<Environment>>) -> Result<Vec<(&'p Expression, &'p Expression)>, Object> {
+    let given = args.len() + keywords.len();
+    let wrong_number = || Object::new_error(format!("wrong number of arguments: want={}, got={}", arity(parameters), given));
+    if let Some((keyword, _)) = keywords.iter()
+        .find(|(keyword, _)| !parameters.iter().any(|parameter| name_of(parameter.parameter_name()) == keyword)) {
+        return Err(Object::new_error(format!("unexpected keyword argument `{}`", keyword)));
+    }
+    let mut args = args.into_iter();
+    let mut defaults = Vec::new();
+    for parameter in parameters {
+        let name = parameter.parameter_name();
+        let keyword = keywords.iter().position(|(keyword, _)| keyword == name_of(name))
+            .map(|idx| keywords.remove(idx).1);
+        let value = match (parameter, args.next(), keyword) {
+            (Expression::Spread{..}, first, None) => {
+                let elements = first.into_iter().chain(args.by_ref()).collect();
+                limits::track(Object::Array{elements})
+            },
+            (Expression::Spread{..}, _, Some(_)) => {
+                return Err(Object::new_error(format!("rest parameter `{}` can't be given by keyword", name_of(name))));
+            },
+            (_, Some(_), Some(_)) => {
+                return Err(Object::new_error(format!("argument `{}` given more than once", name_of(name))));
+            },
+            (_, Some(value), None) | (_, None, Some(value)) => value,
+            (Expression::Named{token:_, name:_, value: default}, None, None) => {
+                defaults.push((name, &**default));
+                continue;
+            },
+            (_, None, None) => return Err(wrong_number())
+        };
+        if value.is_error() {
+            return Err(value);
+        }
+        bind(name, value, environment);
+    }
+    if args.next().is_some() {
+        return Err(wrong_number());
+    }
+    Ok(defaults)
+}
+
+fn bind(name: &Expression, value: Object, environment: &Rc<RefCell<Environment>>) {
+    match name {
+        Expression::Identifier{token:_, value: name} => {
+            environment.borrow_mut().set(name.to_string(), value);
+        },
+        Expression::Local{slot, ..} => {
+            environment.borrow_mut().set_slot(*slot, value);
+        },
+        _ => {}
+    }
+}
+
+/// Why a value didn't fit a pattern: the shapes differ, or something like
+/// an exceeded limit went wrong that even a `match` has to report.
+enum Mismatch {
+    Shape(String),
+    Error(Object)
+}
+
+impl Mismatch {
+    fn into_error(self) -> Object {
+        match self {
+            Mismatch::Shape(message) => Object::new_error(message),
+            Mismatch::Error(error) => error
+        }
+    }
+}
+
+/// Matches `value` against `pattern`, collecting what each name in the
+/// pattern binds. Nothing is bound here, so a failed `match` arm leaves the
+/// environment alone.
+fn match_pattern<'p>(pattern: &'p Pattern, value: &Object, bindings: &mut Vec<(&'p Expression, Object)>) -> Result<(), Mismatch> {
+    with_stack(|| {
+        match pattern {
+            Pattern::Binding(name) => bindings.push((name, value.clone())),
+            Pattern::Wildcard(_) => {},
+            Pattern::Literal(literal) => {
+                let equal = match (literal, value) {
+                    (Expression::IntegerLiteral{token:_, value: expected}, Object::Integer{value}) => expected == value,
+                    (Expression::StringLiteral{token:_, value: expected}, Object::String{value}) => expected == value,
+                    (Expression::Boolean{token:_, value: expected}, Object::Boolean{value}) => expected == value,
+                    _ => false
+                };
+                if !equal {
+                    return Err(Mismatch::Shape(format!("{} does not match the literal pattern", value.type_name())));
+                }
+            },
+            Pattern::Array{token:_, elements: patterns, rest} => {
+                let elements = match value {
+                    Object::Array{elements} => elements,
+                    other => return Err(Mismatch::Shape(format!("cannot destructure {} as an array", other.type_name())))
+                };
+                let enough = match rest {
+                    Some(_) => elements.len() >= patterns.len(),
+                    None => elements.len() == patterns.len()
+                };
+                if !enough {
+                    let want = if rest.is_some() { format!("at least {}", patterns.len()) } else { patterns.len().to_string() };
+                    return Err(Mismatch::Shape(format!("cannot destructure array of {} elements: want {}", elements.len(), want)));
+                }
+                for (pattern, element) in patterns.iter().zip(elements) {
+                    match_pattern(pattern, element, bindings)?;
                 }
-            
+                if let Some(rest) = rest {
+                    let rest_value = limits::track(Object::Array{elements: elements[patterns.len()..].to_vec()});
+                    if rest_value.is_error() {
+                        return Err(Mismatch::Error(rest_value));
+                    }
+                    match_pattern(rest, &rest_value, bindings)?;
+                }
+            },
+            Pattern::Hash{token:_, entries} => {
+                let pairs = match value {
+                    Object::HashM{pairs} => pairs,
+                    other => return Err(Mismatch::Shape(format!("cannot destructure {} as a hash", other.type_name())))
+                };
+                for (key, pattern) in entries {
+                    match pairs.get(&Object::new_string(key.clone())) {
+                        Some(value) => match_pattern(pattern, value, bindings)?,
+                        None => return Err(Mismatch::Shape(format!("cannot destructure hash: missing key \"{}\"", key)))
+                    }
+                }
+            },
+            Pattern::Variant{token:_, name, value: pattern} => {
+                let inner = match (name.as_str(), value) {
+                    ("Ok", Object::Ok{value}) | ("Err", Object::Err{value}) | ("Some", Object::Some{value}) => Some(value),
+                    ("None", Object::None) => None,
+                    _ => return Err(Mismatch::Shape(format!("{} does not match {}", value.type_name(), name)))
+                };
+                if let (Some(pattern), Some(inner)) = (pattern, inner) {
+                    match_pattern(pattern, inner, bindings)?;
+                }
+            },
+            Pattern::Or{token:_, alternatives} => {
+                let mut last = None;
+                for alternative in alternatives {
+                    let mark = bindings.len();
+                    match match_pattern(alternative, value, bindings) {
+                        Ok(()) => return Ok(()),
+                        Err(Mismatch::Shape(message)) => {
+                            bindings.truncate(mark);
+                            last = Some(message);
+                        },
+                        Err(error) => return Err(error)
+                    }
+                }
+                return Err(Mismatch::Shape(last.unwrap_or_default()));
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Where a call was made, for stack traces: `name at line:column`.
+fn describe_call(site: Option<&Expression>) -> String {
+    if let Some(Expression::CallExpression{token, function, arguments:_}) = site {
+        let name = match function.as_ref() {
+            Expression::Identifier{token:_, value} | Expression::Local{token:_, value, ..} => value,
+            Expression::MemberExpression{token:_, object:_, property} => property,
+            _ => "<anonymous>"
+        };
+        return format!("{} at {}:{}", name, token.line, token.column);
+    }
+    "<native call>".to_string()
+}
+
+/// The error raised by `throw value`.
+fn thrown_error(value: Object) -> Object {
+    match &value {
+        Object::String{value} => Object::new_error_of(THROWN_ERROR, value.clone()),
+        Object::HashM{pairs} => {
+            let field = |name: &str| match pairs.get(&Object::new_string(name.to_string())) {
+                Some(Object::String{value}) => Some(value.clone()),
+                _ => None
+            };
+            match field("message") {
+                Some(message) => Object::new_error_of(&field("kind").unwrap_or_else(|| THROWN_ERROR.to_string()), message),
+                None => Object::new_error("cannot throw a hash without a string `message`".to_string())
             }
-            body.evaluate(environment.clone())
         },
-        _ => Object::Null
+        other => Object::new_error(format!("cannot throw {}: want a string or a hash with a message", other.type_name()))
+    }
+}
+
+/// What a `catch` block sees of an error: a hash of its message, kind and
+/// stack.
+fn error_hash(mut error: Object) -> Object {
+    let (message, kind, stack) = match &mut error {
+        Object::Error{message, kind, stack} => (std::mem::take(message), std::mem::take(kind), std::mem::take(stack)),
+        _ => return error
+    };
+    let stack = stack.into_iter().map(Object::new_string).collect();
+    let pairs = vec![
+        ("message", Object::new_string(message)),
+        ("kind", Object::new_string(kind)),
+        ("stack", Object::Array{elements: stack})
+    ];
+    Object::HashM {
+        pairs: pairs.into_iter().map(|(key, value)| (Object::new_string(key.to_string()), value)).collect()
     }
 }
 
 fn eval_index_expression(left: Object, index: Object) -> Object {
-    if let Object::Array{elements} = left {
+    if let Object::Array{elements} = &left {
         if let Object::Integer{value} = index {
             if value >= 0 && (value as usize) < elements.len() {
                 return elements[value as usize].clone();
@@ -155,7 +360,19 @@ fn eval_index_expression(left: Object, index: Object) -> Object {
         }
         return Object::Null;
     }
-    if let Object::HashM{pairs} = left {
+    if let (Object::String{value}, Object::Integer{value: index}) = (&left, &index) {
+        if *index < 0 {
+            return Object::Null;
+        }
+        return match value.chars().nth(*index as usize) {
+            Some(ch) => limits::track(Object::String{value: ch.to_string()}),
+            None => Object::Null
+        };
+    }
+    if let Object::HashM{pairs} = &left {
+        if !index.is_hashable() {
+            return unusable_key(&index);
+        }
         if let Some(result) = pairs.get(&index) {
             return result.clone();
         }
@@ -164,155 +381,938 @@ fn eval_index_expression(left: Object, index: Object) -> Object {
     Object::Null
 }
 
-impl Evaluator for Expression {
+fn unusable_key(key: &Object) -> Object {
+    Object::new_error(format!("unusable as hash key: {}", key.type_name()))
+}
 
-    fn evaluate(&self,environment: Rc<RefCell<Environment>>) -> Object {
-        match self {
-            Expression::IntegerLiteral{
-                 token:_, value
-            } => Object::new_int(*value),
-            Expression::Boolean {
-                token:_, value
-            } => Object::new_bool(*value),
-            Expression::StringLiteral{
-                token: _,
-                value
-            } => Object::new_string(value.clone()),
-            Expression::PrefixExpression {
-                token:_,
-                operator,
-                right
-            } => {
-                let right = right.evaluate(environment);
-                evaluate_prefix_expression(operator, right)
-            },
-            Expression::InfixExpression {
-                token:_,
-                left,
-                operator,
-                right
-            } => {
-                let left = left.evaluate(environment.clone());
-                let right = right.evaluate(environment.clone());
-                evaluate_infix_expression(operator, left, right)
-            },
-            Expression::IfExpression {
-                token:_,
-                condition,
-                consequence,
-                alternative
-            } => {
-                eval_if_expression(condition, consequence, alternative, environment)
-            },
-            Expression::Identifier {token:_, value} => {
-                eval_identifier(value, environment)
-            },
-            Expression::FunctionLiteral {
-                token:_,
-                parameters,
-                body
-            } => {
-                Object::Function {
-                    parameters : parameters.clone(),
-                    body: body.as_ref().clone(),
-                    environment: environment.clone()
+/// Slices arrays by element and strings by character. A negative bound
+/// counts from the end and bounds past either end are clamped.
+pub(crate) fn eval_slice_expression(left: Object, start: Object, end: Object) -> Object {
+    let length = match &left {
+        Object::Array{elements} => elements.len(),
+        Object::String{value} => value.chars().count(),
+        _ => 0
+    };
+    let bound = |bound: Object, default: usize| match bound {
+        Object::Null => Ok(default),
+        Object::Integer{value} if value < 0 => Ok(length.saturating_sub(value.unsigned_abs() as usize)),
+        Object::Integer{value} => Ok(length.min(value as usize)),
+        other => Err(Object::new_error(format!("slice bounds must be INTEGER, got {}", other.type_name())))
+    };
+    let (start, end) = match (bound(start, 0), bound(end, length)) {
+        (Ok(start), Ok(end)) => (start, end.max(start)),
+        (Err(err), _) | (_, Err(err)) => return err
+    };
+    match &left {
+        Object::Array{elements} => Object::Array{elements: elements[start..end].to_vec()},
+        Object::String{value} => Object::String{value: value.chars().skip(start).take(end - start).collect()},
+        other => Object::new_error(format!("cannot slice {}", other.type_name()))
+    }
+}
+
+fn eval_member_expression(object: Object, property: &str) -> Object {
+    if let Object::Native(native) = &object {
+        if let Some(member) = native.member(property) {
+            return member;
+        }
+        return Object::new_error(format!("{} has no property `{}`", native.class.name(), property));
+    }
+    Object::new_error(format!("{} has no property `{}`", object.type_name(), property))
+}
+
+type Env = Rc<RefCell<Environment>>;
+
+/// Keyword arguments of a call, by parameter name.
+type Keywords = Vec<(String, Object)>;
+
+/// A pending step of evaluation. Instead of recursing through the AST,
+/// the evaluator keeps these on a heap-allocated stack, so deep recursion
+/// in Monkey code and deeply nested expressions don't grow the native
+/// stack. `Eval` and `Exec` push a value onto the value stack; the other
+/// steps consume the values their operands left there.
+enum Work<'a> {
+    Eval(&'a Expression, Env),
+    Exec(&'a Statement, Env),
+    /// Drops the result of the previous statement and runs the next one.
+    Block(&'a [Statement], usize, Env),
+    Prefix(&'a str),
+    Infix(&'a str),
+    /// `&&` and `||` short-circuit and give back the operand that decided
+    /// the result, so `name || "anonymous"` picks the first truthy value.
+    Logical(&'a str, &'a Expression, Env),
+    If(&'a Statement, Option<&'a Statement>, Env),
+    /// Calls the function under the arguments; the call expression is
+    /// kept for stack traces.
+    Call(&'a Expression, &'a [Expression]),
+    Array(&'a [Expression]),
+    Template(&'a [String]),
+    Hash(usize),
+    Index,
+    /// Slices the value under its bounds; the flags say which bounds were
+    /// given and so are on the value stack.
+    Slice(bool, bool),
+    Member(&'a str),
+    Propagate,
+    Let(&'a Pattern, Env),
+    /// Binds a parameter left out of a call to the value of its default.
+    Default(&'a Expression, Env),
+    /// Tries the arms of a `match` from the given index on, with the
+    /// subject on top of the value stack.
+    Match(&'a [MatchArm], usize, Env),
+    /// Checks the guard of the arm that matched, in the arm's own frame;
+    /// the guard's value sits on top of the subject.
+    Guard(&'a [MatchArm], usize, Env, Env),
+    Return,
+    Throw,
+    /// The end of a `try` block, with the height of the value stack when it
+    /// started. An error unwinds to here.
+    Try(Option<&'a Catch>, Option<&'a Statement>, usize, Env),
+    /// Drops the value of a `finally` block and carries on with what was
+    /// happening before it ran.
+    Resume(Completion),
+    /// The end of a function call, with the height of the value stack at
+    /// the call and where the call was made. A `return` unwinds to here.
+    Frame(usize, Option<&'a Expression>)
+}
+
+/// How a `try` block or its `catch` ended, held while `finally` runs.
+enum Completion {
+    Value(Object),
+    Error(Object),
+    Return(Object)
+}
+
+/// Keeps the bodies and parameters of called functions alive while they
+/// run.
+#[derive(Default)]
+struct Kept {
+    bodies: Arena<Rc<Statement>>,
+    parameters: Arena<Rc<Vec<Expression>>>
+}
+
+struct Machine<'a> {
+    work: Vec<Work<'a>>,
+    values: Vec<Object>,
+    arenas: &'a Kept,
+    bodies: HashMap<*const Statement, &'a Statement>,
+    parameters: HashMap<*const Vec<Expression>, &'a [Expression]>
+}
+
+impl<'a> Machine<'a> {
+    fn new(arenas: &'a Kept) -> Self {
+        Machine {
+            work: Vec::new(),
+            values: Vec::new(),
+            arenas,
+            bodies: HashMap::new(),
+            parameters: HashMap::new()
+        }
+    }
+
+    fn run(mut self) -> Object {
+        while let Some(work) = self.work.pop() {
+            self.step(work);
+        }
+        self.values.pop().unwrap_or(Object::Null)
+    }
+
+    fn push(&mut self, value: Object) {
+        if value.is_error() {
+            return self.raise(value);
+        }
+        self.values.push(value);
+    }
+
+    fn pop(&mut self) -> Object {
+        self.values.pop().unwrap_or(Object::Null)
+    }
+
+    fn pop_n(&mut self, count: usize) -> Vec<Object> {
+        let start = self.values.len() - count;
+        self.values.split_off(start)
+    }
+
+    /// Unwinds to the nearest `try` that can handle `error`, noting the
+    /// calls it leaves in the error's stack. Without a `try`, all pending
+    /// work is abandoned and the error becomes the result.
+    fn raise(&mut self, mut error: Object) {
+        if let Object::Error{stack, ..} = &mut error {
+            stack.extend(self.work.iter().rev().filter_map(|work| match work {
+                Work::Frame(_, site) => Some(describe_call(*site)),
+                _ => None
+            }));
+        }
+        self.unwind_error(error);
+    }
+
+    /// Errors from exceeded limits go straight past `try`, so scripts
+    /// can't catch their way around a limit, and so does `exit`.
+    fn unwind_error(&mut self, error: Object) {
+        let catchable = !matches!(&error, Object::Error{kind, ..} if kind == LIMIT_ERROR || kind == EXIT_ERROR);
+        while let Some(work) = self.work.pop() {
+            match work {
+                Work::Frame(..) => limits::exit_call(),
+                Work::Try(catch, finally, height, environment) if catchable => {
+                    self.values.truncate(height);
+                    match catch {
+                        Some(catch) => self.catch(catch, finally, height, error, environment),
+                        None => {
+                            self.work.push(Work::Resume(Completion::Error(error)));
+                            self.work.push(Work::Exec(finally.unwrap(), environment));
+                        }
+                    }
+                    return;
+                },
+                _ => {}
+            }
+        }
+        self.values.clear();
+        self.values.push(error);
+    }
+
+    /// Runs a `catch` block with the error bound to its parameter, in a
+    /// frame of its own. The `finally` block, if any, still runs after it.
+    fn catch(&mut self, catch: &'a Catch, finally: Option<&'a Statement>, height: usize, error: Object, environment: Env) {
+        if finally.is_some() {
+            self.work.push(Work::Try(None, finally, height, environment.clone()));
+        }
+        let scope = Rc::new(RefCell::new(Environment::new_enclosed(environment)));
+        if let Some(parameter) = &catch.parameter {
+            let error = error_hash(error);
+            let mut bindings = Vec::new();
+            if let Err(mismatch) = match_pattern(parameter, &error, &mut bindings) {
+                return self.raise(mismatch.into_error());
+            }
+            for (name, value) in bindings {
+                bind(name, value, &scope);
+            }
+        }
+        self.work.push(Work::Exec(&catch.body, scope));
+    }
+
+    /// Abandons the rest of the current function, running the `finally`
+    /// blocks on the way. Outside of a function the `Return` itself
+    /// becomes the result, for the program to unwrap.
+    fn unwind_return(&mut self, value: Object) {
+        while let Some(work) = self.work.pop() {
+            match work {
+                Work::Frame(height, _) => {
+                    limits::exit_call();
+                    self.values.truncate(height);
+                    self.values.push(value);
+                    return;
+                },
+                Work::Try(_, Some(finally), height, environment) => {
+                    self.values.truncate(height);
+                    self.work.push(Work::Resume(Completion::Return(value)));
+                    self.work.push(Work::Exec(finally, environment));
+                    return;
+                },
+                _ => {}
+            }
+        }
+        self.values.clear();
+        self.values.push(Object::Return{value: Box::new(value)});
+    }
+
+    fn block(&mut self, statements: &'a [Statement], environment: Env) {
+        match statements.len() {
+            0 => self.values.push(Object::Null),
+            1 => self.work.push(Work::Exec(&statements[0], environment)),
+            _ => {
+                self.work.push(Work::Block(statements, 1, environment.clone()));
+                self.work.push(Work::Exec(&statements[0], environment));
+            }
+        }
+    }
+
+    fn keep(&mut self, body: &Rc<Statement>) -> &'a Statement {
+        let arenas = self.arenas;
+        self.bodies.entry(Rc::as_ptr(body)).or_insert_with(|| &**arenas.bodies.alloc(body.clone()))
+    }
+
+    fn keep_parameters(&mut self, parameters: &Rc<Vec<Expression>>) -> &'a [Expression] {
+        let arenas = self.arenas;
+        self.parameters.entry(Rc::as_ptr(parameters)).or_insert_with(|| &**arenas.parameters.alloc(parameters.clone()))
+    }
+
+    /// Takes the values of `arguments` off the value stack, expanding
+    /// spreads and setting keyword arguments apart.
+    fn pop_arguments(&mut self, arguments: &[Expression]) -> Result<(Vec<Object>, Keywords), Object> {
+        let values = self.pop_n(arguments.len());
+        let mut positional = Vec::new();
+        let mut keywords = Vec::new();
+        for (argument, mut value) in arguments.iter().zip(values) {
+            match argument {
+                Expression::Spread{..} => match &mut value {
+                    Object::Array{elements} => positional.append(elements),
+                    other => return Err(Object::new_error(format!("cannot spread {}", other.type_name())))
+                },
+                Expression::Named{token:_, name, value: _} => keywords.push((name_of(name).to_string(), value)),
+                _ => positional.push(value)
+            }
+        }
+        Ok((positional, keywords))
+    }
+
+    fn call(&mut self, function: Object, args: Vec<Object>, keywords: Keywords, site: Option<&'a Expression>) {
+        match &function {
+            Object::Builtin(_) if !keywords.is_empty() => {
+                self.raise(Object::new_error("builtins don't take keyword arguments".to_string()))
+            },
+            Object::Builtin(builtin) => {
+                let result = (builtin.as_ref().func)(args);
+                self.push(result);
+            },
+            Object::Function{parameters, body, environment} => {
+                let environment = Rc::new(RefCell::new(Environment::new_enclosed(environment.clone())));
+                let parameters = self.keep_parameters(parameters);
+                let defaults = match bind_parameters(parameters, args, keywords, &environment) {
+                    Ok(defaults) => defaults,
+                    Err(error) => return self.raise(error)
+                };
+                // A call whose value is what the current function returns
+                // replaces that function's frame instead of adding one.
+                match self.work.as_slice() {
+                    [.., Work::Frame(..)] => {
+                        self.work.pop();
+                        limits::exit_call();
+                    },
+                    [.., Work::Frame(..), Work::Return] => {
+                        self.work.truncate(self.work.len() - 2);
+                        limits::exit_call();
+                    },
+                    _ => {}
+                }
+                if let Err(message) = limits::enter_call() {
+                    return self.raise(Object::new_error_of(LIMIT_ERROR, message));
+                }
+                let body = self.keep(body);
+                self.work.push(Work::Frame(self.values.len(), site));
+                self.work.push(Work::Exec(body, environment.clone()));
+                // Defaults run inside the new frame, first to last, so they
+                // count towards the call depth like the body does.
+                for (name, default) in defaults.into_iter().rev() {
+                    self.work.push(Work::Default(name, environment.clone()));
+                    self.work.push(Work::Eval(default, environment.clone()));
                 }
             },
-            Expression::CallExpression {
-                token:_,
-                function,
-                arguments
-            } => {
-                let func = function.evaluate(environment.clone());
-                let args = eval_expressions(arguments, environment.clone());
-                apply_function(func, args)
-            },
-            Expression::ArrayLiteral {
-                token: _,
-                elements
-            } => {
-                let elements = eval_expressions(elements, environment);
-                Object::Array {
-                    elements
+            other => self.raise(Object::new_error(format!("not a function: {}", other.type_name())))
+        }
+    }
+
+    /// Runs the first arm from `first` on whose pattern fits `subject`,
+    /// checking its guard before committing to it. Each arm binds its
+    /// pattern in a frame of its own.
+    fn select_arm(&mut self, arms: &'a [MatchArm], first: usize, subject: Object, environment: Env) {
+        for (idx, arm) in arms.iter().enumerate().skip(first) {
+            let mut bindings = Vec::new();
+            match match_pattern(&arm.pattern, &subject, &mut bindings) {
+                Ok(()) => {},
+                Err(Mismatch::Shape(_)) => continue,
+                Err(Mismatch::Error(error)) => return self.raise(error)
+            }
+            let scope = Rc::new(RefCell::new(Environment::new_enclosed(environment.clone())));
+            for (name, value) in bindings {
+                bind(name, value, &scope);
+            }
+            match &arm.guard {
+                Some(guard) => {
+                    self.values.push(subject);
+                    self.work.push(Work::Guard(arms, idx, environment, scope.clone()));
+                    self.work.push(Work::Eval(guard, scope));
+                },
+                None => self.work.push(Work::Exec(&arm.body, scope))
+            }
+            return;
+        }
+        self.raise(Object::new_error(format!("no match arm matches {}", subject.type_name())));
+    }
+
+    fn step(&mut self, work: Work<'a>) {
+        match work {
+            Work::Eval(expression, environment) => {
+                if let Err(message) = limits::step() {
+                    return self.raise(Object::new_error_of(LIMIT_ERROR, message));
+                }
+                self.eval(expression, environment);
+            },
+            Work::Exec(statement, environment) => self.exec(statement, environment),
+            Work::Block(statements, idx, environment) => {
+                self.values.pop();
+                if idx + 1 < statements.len() {
+                    self.work.push(Work::Block(statements, idx + 1, environment.clone()));
+                }
+                self.work.push(Work::Exec(&statements[idx], environment));
+            },
+            Work::Prefix(operator) => {
+                let right = self.pop();
+                self.push(evaluate_prefix_expression(operator, right));
+            },
+            Work::Infix(operator) => {
+                let right = self.pop();
+                let left = self.pop();
+                self.push(evaluate_infix_expression(operator, left, right));
+            },
+            Work::Logical(operator, right, environment) => {
+                let left = self.pop();
+                match operator {
+                    "&&" if !left.is_truthy() => self.values.push(left),
+                    "||" if left.is_truthy() => self.values.push(left),
+                    _ => self.work.push(Work::Eval(right, environment))
                 }
             },
-            Expression::IndexExpression {
-                token: _,
-                left,
-                index
-            } => {
-                let left = left.evaluate(environment.clone());
-                let index = index.evaluate(environment.clone());
-                eval_index_expression(left, index)
-            },
-            Expression::HashLiteral{
-                token: _,
-                pairs
-            } => {
-                let mut hash_pairs = HashMap::new();
-                for (key, value) in pairs {
-                    let key = key.evaluate(environment.clone());
-                    let value = value.evaluate(environment.clone());
-                    hash_pairs.insert(key, value);
+            Work::If(consequence, alternative, environment) => {
+                let condition = self.pop();
+                if condition.is_truthy() {
+                    self.work.push(Work::Exec(consequence, environment));
+                } else if let Some(alternative) = alternative {
+                    self.work.push(Work::Exec(alternative, environment));
+                } else {
+                    self.values.push(Object::Null);
                 }
-                Object::HashM {
-                    pairs: hash_pairs
+            },
+            Work::Call(site, arguments) => {
+                let arguments = self.pop_arguments(arguments);
+                let function = self.pop();
+                match arguments {
+                    Ok((args, keywords)) => self.call(function, args, keywords, Some(site)),
+                    Err(err) => self.raise(err)
                 }
-            }
-            _ => Object::Null
+            },
+            Work::Array(elements) => match self.pop_arguments(elements) {
+                Ok((elements, _)) => self.push(limits::track(Object::Array{elements})),
+                Err(err) => self.raise(err)
+            },
+            Work::Template(strings) => {
+                let values = self.pop_n(strings.len() - 1);
+                let mut out = strings[0].clone();
+                for (value, string) in values.iter().zip(&strings[1..]) {
+                    out.push_str(&value.to_string());
+                    out.push_str(string);
+                }
+                self.push(limits::track(Object::new_string(out)));
+            },
+            Work::Hash(count) => {
+                let mut items = self.pop_n(count * 2).into_iter();
+                let mut pairs = HashMap::new();
+                while let (Some(key), Some(value)) = (items.next(), items.next()) {
+                    if !key.is_hashable() {
+                        return self.raise(unusable_key(&key));
+                    }
+                    pairs.insert(key, value);
+                }
+                self.push(limits::track(Object::HashM{pairs}));
+            },
+            Work::Index => {
+                let index = self.pop();
+                let left = self.pop();
+                self.push(eval_index_expression(left, index));
+            },
+            Work::Slice(has_start, has_end) => {
+                let end = if has_end { self.pop() } else { Object::Null };
+                let start = if has_start { self.pop() } else { Object::Null };
+                let left = self.pop();
+                self.push(limits::track(eval_slice_expression(left, start, end)));
+            },
+            Work::Propagate => match self.pop() {
+                mut value @ (Object::Ok{..} | Object::Some{..}) => self.values.push(value.take_inner().unwrap_or_default()),
+                value @ (Object::Err{..} | Object::None) => self.unwind_return(value),
+                other => self.raise(Object::new_error(format!("`?` needs an Ok, Err, Some or None, got {}", other.type_name())))
+            },
+            Work::Member(property) => {
+                let object = self.pop();
+                self.push(eval_member_expression(object, property));
+            },
+            Work::Let(name, environment) => {
+                let value = self.pop();
+                let mut bindings = Vec::new();
+                if let Err(mismatch) = match_pattern(name, &value, &mut bindings) {
+                    return self.raise(mismatch.into_error());
+                }
+                for (name, value) in bindings {
+                    bind(name, value, &environment);
+                }
+                self.values.push(Object::Null);
+            },
+            Work::Default(name, environment) => {
+                let value = self.pop();
+                bind(name, value, &environment);
+            },
+            Work::Match(arms, first, environment) => {
+                let subject = self.pop();
+                self.select_arm(arms, first, subject, environment);
+            },
+            Work::Guard(arms, idx, environment, scope) => {
+                let guard = self.pop();
+                let subject = self.pop();
+                if guard.is_truthy() {
+                    self.work.push(Work::Exec(&arms[idx].body, scope));
+                } else {
+                    self.select_arm(arms, idx + 1, subject, environment);
+                }
+            },
+            Work::Return => {
+                let value = self.pop();
+                self.unwind_return(value);
+            },
+            Work::Throw => {
+                let value = self.pop();
+                self.raise(thrown_error(value));
+            },
+            Work::Try(_, finally, _, environment) => {
+                if let Some(finally) = finally {
+                    let value = self.pop();
+                    self.work.push(Work::Resume(Completion::Value(value)));
+                    self.work.push(Work::Exec(finally, environment));
+                }
+            },
+            Work::Resume(completion) => {
+                self.values.pop();
+                match completion {
+                    Completion::Value(value) => self.values.push(value),
+                    Completion::Error(error) => self.unwind_error(error),
+                    Completion::Return(value) => self.unwind_return(value)
+                }
+            },
+            Work::Frame(..) => limits::exit_call()
         }
     }
-}
 
-impl Evaluator for Program {
-    fn evaluate(&self, environment: Rc<RefCell<Environment>>) -> Object {
-        let mut result = Object::Null;
-        for st in &self.statements {
-            result = st.evaluate(environment.clone());
-            if let Object::Return{value} = result {
-                return *value;
+    fn exec(&mut self, statement: &'a Statement, environment: Env) {
+        match statement {
+            Statement::ExpressionStatement{token:_, expression} => {
+                self.work.push(Work::Eval(expression, environment));
+            },
+            Statement::BlockStatment{token:_, statements} => self.block(statements, environment),
+            Statement::ReturnStatement{token:_, return_value} => {
+                self.work.push(Work::Return);
+                self.work.push(Work::Eval(return_value, environment));
+            },
+            Statement::ThrowStatement{token:_, value} => {
+                self.work.push(Work::Throw);
+                self.work.push(Work::Eval(value, environment));
+            },
+            Statement::LetStatement{token:_, name, value} => {
+                self.work.push(Work::Let(name, environment.clone()));
+                self.work.push(Work::Eval(value, environment));
             }
         }
-        result
     }
-}
 
-impl Evaluator for Statement {
-    fn evaluate(&self, environment: Rc<RefCell<Environment>>) -> Object {
-        match self {
-            Statement::ExpressionStatement{
-                token:_,
-                expression
-            } => expression.evaluate(environment),
-            Statement::BlockStatment{token:_, statements} => {
-                eval_block_statements(statements, environment)
+    fn eval(&mut self, expression: &'a Expression, environment: Env) {
+        match expression {
+            Expression::IntegerLiteral{token:_, value} => self.values.push(Object::new_int(*value)),
+            Expression::Boolean{token:_, value} => self.values.push(Object::new_bool(*value)),
+            Expression::StringLiteral{token:_, value} => {
+                self.push(limits::track(Object::new_string(value.clone())));
             },
-            Statement::ReturnStatement{token:_, return_value} => {
-                let value = return_value.evaluate(environment);
-                Object::Return {
-                    value: Box::new(value)
+            Expression::PrefixExpression{token:_, operator, right} => {
+                self.work.push(Work::Prefix(operator));
+                self.work.push(Work::Eval(right, environment));
+            },
+            Expression::InfixExpression{token:_, left, operator, right} if operator == "&&" || operator == "||" => {
+                self.work.push(Work::Logical(operator, right, environment.clone()));
+                self.work.push(Work::Eval(left, environment));
+            },
+            Expression::InfixExpression{token:_, left, operator, right} => {
+                self.work.push(Work::Infix(operator));
+                self.work.push(Work::Eval(right, environment.clone()));
+                self.work.push(Work::Eval(left, environment));
+            },
+            Expression::MatchExpression{token:_, subject, arms} => {
+                self.work.push(Work::Match(arms, 0, environment.clone()));
+                self.work.push(Work::Eval(subject, environment));
+            },
+            Expression::IfExpression{token:_, condition, consequence, alternative} => {
+                self.work.push(Work::If(consequence, alternative.as_deref(), environment.clone()));
+                self.work.push(Work::Eval(condition, environment));
+            },
+            Expression::Identifier{token:_, value} => {
+                let value = eval_identifier(value, environment);
+                self.values.push(value);
+            },
+            Expression::Local{token:_, value:_, depth, slot} => {
+                let value = eval_local(*depth, *slot, environment);
+                self.values.push(value);
+            },
+            Expression::FunctionLiteral{token:_, parameters, body} => {
+                self.values.push(Object::Function {
+                    parameters: Rc::new(parameters.clone()),
+                    body: body.clone(),
+                    environment
+                });
+            },
+            Expression::TryExpression{token:_, body, catch, finally} => {
+                let height = self.values.len();
+                self.work.push(Work::Try(catch.as_deref(), finally.as_deref(), height, environment.clone()));
+                self.work.push(Work::Exec(body, environment));
+            },
+            Expression::CallExpression{token:_, function, arguments} => {
+                self.work.push(Work::Call(expression, arguments));
+                self.eval_all(arguments, &environment);
+                self.work.push(Work::Eval(function, environment));
+            },
+            Expression::TemplateLiteral{token:_, strings, values} => {
+                self.work.push(Work::Template(strings));
+                for value in values.iter().rev() {
+                    self.work.push(Work::Eval(value, environment.clone()));
                 }
             },
-            Statement::LetStatement {token:_, name, value} => {
-                let value = value.evaluate(environment.clone());
-                if let Expression::Identifier{token:_, value:name} = name {
-                    (*environment.borrow_mut()).set(name.as_str().to_string(), value);
+            Expression::ArrayLiteral{token:_, elements} => {
+                self.work.push(Work::Array(elements));
+                self.eval_all(elements, &environment);
+            },
+            Expression::IndexExpression{token:_, left, index} => {
+                self.work.push(Work::Index);
+                self.work.push(Work::Eval(index, environment.clone()));
+                self.work.push(Work::Eval(left, environment));
+            },
+            Expression::SliceExpression{token:_, left, start, end} => {
+                self.work.push(Work::Slice(start.is_some(), end.is_some()));
+                for bound in end.iter().chain(start) {
+                    self.work.push(Work::Eval(bound, environment.clone()));
                 }
-                
-                Object::Null
-            }
-            _ => Object::Null
+                self.work.push(Work::Eval(left, environment));
+            },
+            Expression::Propagate{token:_, value} => {
+                self.work.push(Work::Propagate);
+                self.work.push(Work::Eval(value, environment));
+            },
+            Expression::MemberExpression{token:_, object, property} => {
+                self.work.push(Work::Member(property));
+                self.work.push(Work::Eval(object, environment));
+            },
+            Expression::HashLiteral{token:_, pairs} => {
+                self.work.push(Work::Hash(pairs.len()));
+                for (key, value) in pairs.iter().rev() {
+                    self.work.push(Work::Eval(value, environment.clone()));
+                    self.work.push(Work::Eval(key, environment.clone()));
+                }
+            },
+            Expression::Spread{..} => {
+                self.raise(Object::new_error("`...` is only allowed in calls and array literals".to_string()))
+            },
+            Expression::Named{..} => {
+                self.raise(Object::new_error("`name = value` is only allowed in calls".to_string()))
+            },
+            Expression::Nope => self.values.push(Object::Null)
         }
     }
-}
 
-fn eval_block_statements(statements: &Vec<Statement>, environment: Rc<RefCell<Environment>>) -> Object {
-    let mut result = Object::Null;
-    for st in statements {
-        result = st.evaluate(environment.clone());
-        if let Object::Return{value} = result {
-            return *value;
+    /// Schedules `expressions` to be evaluated left to right. An error in
+    /// one of them abandons the rest. Spreads and keyword arguments leave
+    /// just their value, for `pop_arguments` to sort out.
+    fn eval_all(&mut self, expressions: &'a [Expression], environment: &Env) {
+        for expression in expressions.iter().rev() {
+            let expression = match expression {
+                Expression::Spread{token:_, value} | Expression::Named{token:_, name:_, value} => value,
+                expression => expression
+            };
+            self.work.push(Work::Eval(expression, environment.clone()));
         }
     }
+}
+
+/// Calls a Monkey function or builtin. Errors come back as `Object::Error`;
+/// Rust code calls through `convert::Callable`, which returns a `Result`
+/// and puts its limits in force.
+pub(crate) fn apply_function(function: Object, args: Vec<Object>) -> Object {
+    if let Err(message) = limits::enter_callback() {
+        return Object::new_error_of(LIMIT_ERROR, message);
+    }
+    let result = with_stack(|| {
+        let arenas = Kept::default();
+        let mut machine = Machine::new(&arenas);
+        machine.call(function, args, Vec::new(), None);
+        machine.run()
+    });
+    limits::exit_callback();
     result
-}
\ No newline at end of file
+}
+
+impl Evaluator for Expression {
+    fn evaluate(&self, environment: Rc<RefCell<Environment>>) -> Object {
+        let arenas = Kept::default();
+        let mut machine = Machine::new(&arenas);
+        machine.work.push(Work::Eval(self, environment));
+        machine.run()
+    }
+}
+
+impl Evaluator for Program {
+    fn evaluate(&self, environment: Rc<RefCell<Environment>>) -> Object {
+        let arenas = Kept::default();
+        let mut machine = Machine::new(&arenas);
+        machine.block(&self.statements, environment);
+        machine.run().unwrap_return()
+    }
+}
+
+/// A `return` outside of a function comes back as `Object::Return`.
+impl Evaluator for Statement {
+    fn evaluate(&self, environment: Rc<RefCell<Environment>>) -> Object {
+        let arenas = Kept::default();
+        let mut machine = Machine::new(&arenas);
+        machine.work.push(Work::Exec(self, environment));
+        machine.run()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use crate::builtins::Builtins;
+    use crate::interpreter::{Error, Interpreter};
+    use crate::object::Object;
+
+    fn eval(source: &str) -> Result<Object, Error> {
+        Interpreter::new().eval_str(source)
+    }
+
+    fn runtime_error(message: &str) -> Result<Object, Error> {
+        Err(Error::Runtime(message.to_string()))
+    }
+
+    fn string(value: &str) -> Object {
+        Object::new_string(value.to_string())
+    }
+
+    /// Evaluates `source` with a `log(step)` builtin and returns the
+    /// steps it logged, in order.
+    fn eval_logged(source: &str) -> (Result<Object, Error>, Vec<String>) {
+        let steps = Rc::new(RefCell::new(Vec::new()));
+        let log = steps.clone();
+        let mut builtins = Builtins::new();
+        builtins.register_fn("log", move |step: String| {
+            log.borrow_mut().push(step);
+            Object::Null
+        });
+        let result = Interpreter::with_builtins(builtins).eval_str(source);
+        let steps = steps.borrow().clone();
+        (result, steps)
+    }
+
+    #[test]
+    fn truthiness_is_the_same_everywhere() {
+        let cases = [
+            ("if (false) { 1 }", false),
+            ("false", false),
+            ("0", false),
+            ("\"\"", false),
+            ("[]", false),
+            ("{}", false),
+            ("None", false),
+            ("Err(1)", false),
+            ("true", true),
+            ("1", true),
+            ("-1", true),
+            ("\"a\"", true),
+            ("[0]", true),
+            ("{0: 0}", true),
+            ("Ok(0)", true),
+            ("Some(false)", true),
+            ("fn() {}", true),
+            ("len", true)
+        ];
+        for (value, truthy) in cases {
+            let with = |expression: &str| eval(&format!("let v = {}; {}", value, expression)).map(|result| result.to_string());
+            let itself = with("v");
+            assert_eq!(with("if (v) { true } else { false }"), Ok(truthy.to_string()), "if ({})", value);
+            assert_eq!(with("!v"), Ok((!truthy).to_string()), "!{}", value);
+            assert_eq!(with("!!v"), Ok(truthy.to_string()), "!!{}", value);
+            assert_eq!(with("v && \"and\""), if truthy { Ok("and".to_string()) } else { itself.clone() }, "{} && ..", value);
+            assert_eq!(with("v || \"or\""), if truthy { itself.clone() } else { Ok("or".to_string()) }, "{} || ..", value);
+        }
+    }
+
+    #[test]
+    fn logical_operators_short_circuit() {
+        let (result, steps) = eval_logged("[0 && log(\"and\"), 1 || log(\"or\"), 1 && log(\"both\")]");
+        assert_eq!(result.map(|r| r.to_string()), Ok("[0, 1, null]".to_string()));
+        assert_eq!(steps, ["both"]);
+    }
+
+    #[test]
+    fn tail_calls_do_not_grow_the_call_depth() {
+        assert_eq!(eval("let count = fn(n) { if (n == 0) { \"done\" } else { count(n - 1) } }; count(200000)"),
+            Ok(string("done")));
+        assert_eq!(eval("let count = fn(n) { if (n == 0) { 0 } else { return count(n - 1); } }; count(200000)"),
+            Ok(Object::new_int(0)));
+        assert_eq!(eval("let count = fn(n) { if (n == 0) { 0 } else { 1 + count(n - 1) } }; count(200000)"),
+            runtime_error("limit exceeded: call depth over 100000"));
+    }
+
+    #[test]
+    fn finally_runs_while_unwinding() {
+        let (result, steps) = eval_logged("let f = fn() { try { log(\"try\"); return 1; log(\"after\") } finally { log(\"finally\") } }; f()");
+        assert_eq!((result, steps), (Ok(Object::new_int(1)), vec!["try".to_string(), "finally".to_string()]));
+
+        let source = "let g = fn() { try { throw \"boom\" } finally { log(\"g\") } };
+            let f = fn() { try { g() } finally { log(\"f\") } };
+            try { f() } catch (e) { log(\"catch\"); e[\"message\"] } finally { log(\"outer\") }";
+        let (result, steps) = eval_logged(source);
+        assert_eq!(result, Ok(string("boom")));
+        assert_eq!(steps, ["g", "f", "catch", "outer"]);
+
+        let (result, steps) = eval_logged("try { throw \"boom\" } finally { log(\"finally\") }");
+        assert_eq!((result, steps), (runtime_error("boom"), vec!["finally".to_string()]));
+    }
+
+    #[test]
+    fn question_mark_returns_err_and_none() {
+        let half = "let half = fn(n) { if (n / 2 * 2 == n) { Ok(n / 2) } else { Err(\"odd\") } };
+            let quarter = fn(n) { let h = half(n)?; half(h) };";
+        assert_eq!(eval(&format!("{} quarter(8)", half)).map(|r| r.to_string()), Ok("Ok(2)".to_string()));
+        assert_eq!(eval(&format!("{} quarter(6)", half)).map(|r| r.to_string()), Ok("Err(\"odd\")".to_string()));
+        assert_eq!(eval("let double = fn(x) { Some(x? * 2) }; [double(Some(2)), double(None)]").map(|r| r.to_string()),
+            Ok("[Some(4), None]".to_string()));
+        assert_eq!(eval("let f = fn() { 5? }; f()"), runtime_error("`?` needs an Ok, Err, Some or None, got INTEGER"));
+    }
+
+    #[test]
+    fn compares_ok_err_some_and_none() {
+        let cases = [
+            ("None == None", "true"),
+            ("let x = None; x != None", "false"),
+            ("Some(1) == Some(1)", "true"),
+            ("Some(1) == Some(2)", "false"),
+            ("Ok([1, \"a\"]) == Ok([1, \"a\"])", "true"),
+            ("Ok(1) == Err(1)", "false"),
+            ("Some(1) != None", "true"),
+            ("1 == Some(1)", "false")
+        ];
+        for (source, expected) in cases {
+            assert_eq!(eval(source).map(|r| r.to_string()), Ok(expected.to_string()), "{}", source);
+        }
+    }
+
+    #[test]
+    fn interpolates_expressions() {
+        let cases = [
+            (r#""a ${1 + 2} b""#, "a 3 b"),
+            (r#"let name = "x"; "${name}${name}""#, "xx"),
+            (r#""${ "in" + "ner" }!""#, "inner!"),
+            (r#""${ {"k": "v"}["k"] }""#, "v"),
+            (r#""outer ${"inner ${1 + 1}"}""#, "outer inner 2"),
+            (r#""${[1, "a"]} ${Some("s")} ${true}""#, r#"[1, "a"] Some("s") true"#),
+            (r#""cost: \${x} ${1}""#, "cost: ${x} 1"),
+            (r#""\${1}""#, "${1}"),
+            (r#""a \$ b $ {c}""#, r"a \$ b $ {c}"),
+            (r#""""#, "")
+        ];
+        for (source, expected) in cases {
+            assert_eq!(eval(source), Ok(string(expected)), "{}", source);
+        }
+        assert_eq!(eval(r#""x ${1 / 0} y""#), runtime_error("division by zero"));
+        assert_eq!(eval(r#"let fail = fn() { throw "boom" }; try { "${fail()}" } catch (e) { e["message"] }"#), Ok(string("boom")));
+        assert_eq!(eval(r#""${missing}""#), Err(Error::Resolve(vec!["1:4: undefined variable `missing`".to_string()])));
+        assert_eq!(eval(r#""${}""#), Err(Error::Parse("1:4: expected expression inside ${}".to_string())));
+        assert_eq!(eval(r#""${1 2}""#), Err(Error::Parse("1:6: expected } after interpolated expression".to_string())));
+    }
+
+    #[test]
+    fn match_takes_values_apart() {
+        let cases = [
+            ("match ([1, [2, 3]]) { [a, [b, c]] => a + b + c, _ => 0 }", "6"),
+            ("match ([1, 2, 3]) { [first, ...rest] => [first, rest] }", "[1, [2, 3]]"),
+            ("match ({\"name\": \"x\", \"age\": 3}) { {name, age: years} => [name, years] }", "[\"x\", 3]"),
+            ("match (Ok(5)) { Err(_) => 0, Ok(n) if n > 7 => 1, Ok(n) => n }", "5"),
+            ("match (2) { 1 | 2 => \"small\", _ => \"big\" }", "small")
+        ];
+        for (source, expected) in cases {
+            assert_eq!(eval(source).map(|r| r.to_string()), Ok(expected.to_string()), "{}", source);
+        }
+        assert_eq!(eval("match (3) { 1 => 0 }"), runtime_error("no match arm matches INTEGER"));
+    }
+
+    #[test]
+    fn binds_default_rest_and_keyword_parameters() {
+        let cases = [
+            ("let f = fn(a, b = a * 2) { [a, b] }; [f(1), f(1, 5)]", "[[1, 2], [1, 5]]"),
+            ("let f = fn(a, b = 2, c = 3) { [a, b, c] }; f(1, c = 4)", "[1, 2, 4]"),
+            ("let f = fn(a, b) { a - b }; f(b = 1, a = 5)", "4"),
+            ("let f = fn(a, ...rest) { [a, rest] }; [f(1), f(1, 2, 3)]", "[[1, []], [1, [2, 3]]]"),
+            ("let f = fn(a, b, c) { a + b + c }; let xs = [2, 3]; f(1, ...xs)", "6"),
+            ("let f = fn(...all) { all }; f(...[1, 2], 3, ...[])", "[1, 2, 3]"),
+            ("[0, ...[1, 2], 3]", "[0, 1, 2, 3]")
+        ];
+        for (source, expected) in cases {
+            assert_eq!(eval(source).map(|r| r.to_string()), Ok(expected.to_string()), "{}", source);
+        }
+    }
+
+    #[test]
+    fn reports_bad_calls() {
+        let cases = [
+            ("let f = fn(a, b) { a }; f(1)", "wrong number of arguments: want=2, got=1"),
+            ("let f = fn() { 0 }; f(1)", "wrong number of arguments: want=0, got=1"),
+            ("let f = fn(a, b = 2) { a }; f()", "wrong number of arguments: want=1 to 2, got=0"),
+            ("let f = fn(a, b = 2) { a }; f(1, 2, 3)", "wrong number of arguments: want=1 to 2, got=3"),
+            ("let f = fn(a, ...r) { a }; f()", "wrong number of arguments: want=at least 1, got=0"),
+            ("let f = fn(a) { a }; f(b = 1)", "unexpected keyword argument `b`"),
+            ("let f = fn(a) { a }; f(1, a = 2)", "argument `a` given more than once"),
+            ("let f = fn(...r) { r }; f(r = 1)", "rest parameter `r` can't be given by keyword"),
+            ("let f = fn(a) { a }; f(...1)", "cannot spread INTEGER"),
+            ("len(x = 1)", "builtins don't take keyword arguments"),
+            ("let f = fn(a = 1 / 0) { a }; f()", "division by zero")
+        ];
+        for (source, message) in cases {
+            assert_eq!(eval(source), runtime_error(message), "{}", source);
+        }
+    }
+
+    #[test]
+    fn defaults_do_not_use_the_native_stack() {
+        let f = "let f = fn(n, d = if (n > 0) { f(n - 1) } else { 0 }) { d };";
+        assert_eq!(eval(&format!("{} f(50000)", f)), Ok(Object::new_int(0)));
+        assert_eq!(eval(&format!("{} f(200000)", f)), runtime_error("limit exceeded: call depth over 100000"));
+    }
+
+    #[test]
+    fn let_takes_values_apart() {
+        assert_eq!(eval("let [a, {b}] = [1, {\"b\": 2}]; a + b"), Ok(Object::new_int(3)));
+        assert_eq!(eval("let [first, ...rest] = [1, 2, 3]; rest").map(|r| r.to_string()), Ok("[2, 3]".to_string()));
+        assert_eq!(eval("let [a, b] = [1]; a"), runtime_error("cannot destructure array of 1 elements: want 2"));
+    }
+
+    #[test]
+    fn arithmetic_errors_are_runtime_errors() {
+        assert_eq!(eval("let z = 0; 5 / z"), runtime_error("division by zero"));
+        assert_eq!(eval("let m = 9223372036854775807; m + 1"), runtime_error("integer overflow: 9223372036854775807 + 1"));
+        assert_eq!(eval("let m = -9223372036854775807 - 1; m * -1"),
+            runtime_error("integer overflow: -9223372036854775808 * -1"));
+        assert_eq!(eval("let m = -9223372036854775807 - 1; -m"), runtime_error("integer overflow: -(-9223372036854775808)"));
+        assert_eq!(eval("try { let z = 0; 1 / z } catch (e) { e[\"message\"] }"), Ok(Object::new_string("division by zero".to_string())));
+    }
+
+    #[test]
+    fn unhashable_keys_are_runtime_errors() {
+        assert_eq!(eval("{fn(){}: 1}"), runtime_error("unusable as hash key: FUNCTION"));
+        assert_eq!(eval("{}[puts]"), runtime_error("unusable as hash key: BUILTIN"));
+        assert_eq!(eval("{[1]: 2}[[fn(){}]]"), runtime_error("unusable as hash key: ARRAY"));
+        assert_eq!(eval("{[1, \"a\"]: 2}[[1, \"a\"]]"), Ok(Object::new_int(2)));
+    }
+
+    #[test]
+    fn handles_deeply_nested_values() {
+        let deep = format!("{}1{}", "[".repeat(100_000), "]".repeat(100_000));
+        assert_eq!(eval(&format!("len({})", deep)), Ok(Object::new_int(1)));
+        assert_eq!(eval(&format!("let x = {}; len(\"${{x}}\")", deep)), Ok(Object::new_int(200_001)));
+        assert_eq!(eval(&format!("let x = {}; {{x: 2}}[x]", deep)), Ok(Object::new_int(2)));
+        let nest = "let nest = fn(a, n) { if (n == 0) { a } else { nest([a], n - 1) } };";
+        assert_eq!(eval(&format!("{} let x = nest(1, 3000); 1", nest)), Ok(Object::new_int(1)));
+    }
+
+    #[test]
+    fn evaluates_deeply_nested_programs() {
+        let nested = |open: &str, inner: &str, close: &str| format!("{}{}{}", open.repeat(10_000), inner, close.repeat(10_000));
+        assert_eq!(eval(&format!("len({})", nested("[", "1", "]"))), Ok(Object::new_int(1)));
+        assert_eq!(eval(&nested("if (true) { ", "1", " }")), Ok(Object::new_int(1)));
+        assert_eq!(eval(&nested("match (1) { _ => ", "1", " }")), Ok(Object::new_int(1)));
+        assert_eq!(eval(&format!("let f = fn(x) {{ {} }}; f(1)", nested("fn(x) { ", "x", " }(x)"))), Ok(Object::new_int(1)));
+        assert_eq!(eval(&format!("let f = fn() {{ let {} = {}; a }}; f()", nested("[", "a", "]"), nested("[", "1", "]"))),
+            Ok(Object::new_int(1)));
+    }
+}
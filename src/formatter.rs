@@ -0,0 +1,589 @@
+use std::collections::HashMap;
+
+use crate::ast::{Expression, MatchArm, Pattern, Statement};
+use crate::lexer::{Comment, Lexer, Token, TokenType};
+use crate::parser::{Error, Parser, CALL, INDEX, LOWEST, PREFIX};
+use crate::visitor::with_stack;
+
+const INDENT: &str = "    ";
+const MAX_WIDTH: usize = 100;
+
+type Position = (usize, usize);
+
+/// Opinionated pretty printer for Monkey source.
+///
+/// The program is parsed and printed back from the AST, so spacing,
+/// indentation and semicolons are normalised. Comments are not part of the
+/// AST; they are taken from the lexer and re-attached to the statement that
+/// follows them, or kept on the same line when they trail a statement.
+pub struct Formatter {
+    source: String,
+    tokens: Vec<Token>,
+    index: HashMap<Position, usize>,
+    /// Closing brace, bracket or parenthesis of each opening one.
+    closing: HashMap<Position, Position>,
+    comments: Vec<Comment>,
+    next_comment: usize,
+    precedences: HashMap<TokenType, u32>,
+    // Width of the text already on the current line, used to decide
+    // whether a list still fits.
+    lead: usize,
+    // Set while an item of a list is tried on one line. The item is only
+    // used if its first line fits, so a list inside it that has to break
+    // and cannot fit even its opening token there stops early.
+    fitting: bool
+}
+
+pub fn format_source(source: &str) -> Result<String, Error> {
+    let mut parser = Parser::new(Lexer::new(source.to_string()));
+    let program = parser.parse_program()?;
+    let mut formatter = Formatter::new(source);
+    let eof = formatter.tokens.last().unwrap().position();
+    let mut out = formatter.statements(&program.statements, 0, eof, false);
+    while out.ends_with("\n\n") {
+        out.pop();
+    }
+    Ok(out)
+}
+
+impl Formatter {
+    fn new(source: &str) -> Self {
+        let mut lexer = Lexer::new(source.to_string());
+        let mut tokens = Vec::new();
+        loop {
+            let token = lexer.next_token();
+            let eof = token.token_type == TokenType::EOF;
+            tokens.push(token);
+            if eof {
+                break;
+            }
+        }
+
+        let mut index = HashMap::new();
+        let mut closing = HashMap::new();
+        let mut open = Vec::new();
+        for (idx, token) in tokens.iter().enumerate() {
+            index.insert(token.position(), idx);
+            match token.token_type {
+                TokenType::LBRACE | TokenType::LBRACKET | TokenType::LPAREN => open.push(token.position()),
+                TokenType::RBRACE | TokenType::RBRACKET | TokenType::RPAREN => {
+                    if let Some(start) = open.pop() {
+                        closing.insert(start, token.position());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Formatter {
+            source: source.to_string(),
+            tokens,
+            index,
+            closing,
+            comments: lexer.comments().to_vec(),
+            next_comment: 0,
+            precedences: Parser::get_precedences(),
+            lead: 0,
+            fitting: false
+        }
+    }
+
+    /// Line of the last token that comes before `position`.
+    fn line_before(&self, position: Position) -> usize {
+        match self.index.get(&position) {
+            Some(idx) if *idx > 0 => self.tokens[idx - 1].line,
+            _ => position.0
+        }
+    }
+
+    fn take_comment_before(&mut self, position: Position) -> Option<Comment> {
+        let comment = self.comments.get(self.next_comment)?;
+        if comment.position() < position {
+            self.next_comment += 1;
+            return Some(comment.clone());
+        }
+        None
+    }
+
+    fn take_comment_on_line(&mut self, line: usize, position: Position) -> Option<Comment> {
+        let comment = self.comments.get(self.next_comment)?;
+        if comment.line == line && comment.position() < position {
+            self.next_comment += 1;
+            return Some(comment.clone());
+        }
+        None
+    }
+
+    /// Prints a run of statements at `indent`, including every comment that
+    /// appears before `end` (a closing brace or EOF). In a block the final
+    /// expression statement is the block's value and is left without `;`.
+    fn statements(&mut self, statements: &[Statement], indent: usize, end: Position, block: bool) -> String {
+        let pad = INDENT.repeat(indent);
+        let mut out = String::new();
+        let mut last_line: Option<usize> = None;
+        self.fitting = false;
+        for (idx, statement) in statements.iter().enumerate() {
+            let start = statement.token().position();
+            while let Some(comment) = self.take_comment_before(start) {
+                push_blank_line(&mut out, last_line, comment.line);
+                out.push_str(&format!("{}{}\n", pad, comment.text));
+                last_line = Some(comment.line);
+            }
+            push_blank_line(&mut out, last_line, start.0);
+
+            let next = statements.get(idx + 1)
+                .map(|st| st.token().position())
+                .unwrap_or(end);
+            out.push_str(&pad);
+            self.lead = pad.len();
+            let tail = block && idx + 1 == statements.len();
+            out.push_str(&self.statement(statement, indent, tail));
+            let end_line = self.line_before(next);
+            if let Some(comment) = self.take_comment_on_line(end_line, next) {
+                out.push_str(&format!(" {}", comment.text));
+            }
+            out.push('\n');
+            last_line = Some(end_line);
+        }
+        while let Some(comment) = self.take_comment_before(end) {
+            push_blank_line(&mut out, last_line, comment.line);
+            out.push_str(&format!("{}{}\n", pad, comment.text));
+            last_line = Some(comment.line);
+        }
+        out
+    }
+
+    fn statement(&mut self, statement: &Statement, indent: usize, tail: bool) -> String {
+        with_stack(|| {
+            match statement {
+                Statement::LetStatement{token:_, name, value} => {
+                    let lead = self.lead;
+                    self.lead += "let ".len();
+                    let head = format!("let {} = ", self.pattern(name, indent));
+                    self.lead = lead + head.len();
+                    format!("{}{};", head, self.expression(value, indent))
+                },
+                Statement::ReturnStatement{token:_, return_value} => {
+                    self.lead += "return ".len();
+                    format!("return {};", self.expression(return_value, indent))
+                },
+                Statement::ThrowStatement{token:_, value} => {
+                    self.lead += "throw ".len();
+                    format!("throw {};", self.expression(value, indent))
+                },
+                Statement::ExpressionStatement{token:_, expression} => {
+                    let expr = self.expression(expression, indent);
+                    if tail {
+                        return expr;
+                    }
+                    if let Expression::IfExpression{..} | Expression::MatchExpression{..} | Expression::TryExpression{..} = expression {
+                        return expr;
+                    }
+                    format!("{};", expr)
+                },
+                Statement::BlockStatment{..} => self.block(statement, indent)
+            }
+        })
+    }
+
+    fn block(&mut self, block: &Statement, indent: usize) -> String {
+        if let Statement::BlockStatment{token, statements} = block {
+            let end = self.closing.get(&token.position()).cloned().unwrap_or((usize::MAX, 0));
+            let body = self.statements(statements, indent + 1, end, true);
+            if body.is_empty() {
+                return "{}".to_string();
+            }
+            return format!("{{\n{}{}}}", body, INDENT.repeat(indent));
+        }
+        self.statement(block, indent, false)
+    }
+
+    fn expression(&mut self, expression: &Expression, indent: usize) -> String {
+        with_stack(|| {
+            match expression {
+                Expression::Nope => String::new(),
+                Expression::Identifier{token:_, value} => value.clone(),
+                Expression::Local{token:_, value, ..} => value.clone(),
+                Expression::IntegerLiteral{token:_, value} => value.to_string(),
+                // Raw and triple-quoted strings are kept as written.
+                Expression::StringLiteral{token, value} => match self.source.get(token.span.0..token.span.1) {
+                    Some(text) if !text.is_empty() => text.to_string(),
+                    _ => format!("\"{}\"", value)
+                },
+                Expression::TemplateLiteral{token:_, strings, values} => {
+                    let lead = self.lead;
+                    let mut out = format!("\"{}", strings[0].replace("${", "\\${"));
+                    for (value, string) in values.iter().zip(&strings[1..]) {
+                        self.lead = lead + out.len() + "${".len();
+                        out.push_str(&format!("${{{}}}{}", self.expression(value, indent), string.replace("${", "\\${")));
+                    }
+                    out.push('"');
+                    out
+                },
+                Expression::Boolean{token:_, value} => value.to_string(),
+                Expression::ArrayLiteral{token, elements} => {
+                    self.list(token.position(), "[", elements, "]", indent)
+                },
+                Expression::HashLiteral{token, pairs} => {
+                    let starts: Vec<Position> = pairs.iter().map(|(key, _)| expression_start(key)).collect();
+                    self.wrap(token.position(), "{", "}", &starts, indent, |this, idx, indent| {
+                        let (key, value) = &pairs[idx];
+                        let key = this.expression(key, indent);
+                        this.lead += key.len() + ": ".len();
+                        format!("{}: {}", key, this.expression(value, indent))
+                    })
+                },
+                Expression::IndexExpression{token:_, left, index} => {
+                    format!("{}[{}]", self.operand(left, CALL, indent), self.expression(index, indent))
+                },
+                Expression::SliceExpression{token:_, left, start, end} => {
+                    let left = self.operand(left, CALL, indent);
+                    let start = start.as_ref().map(|start| self.expression(start, indent)).unwrap_or_default();
+                    let end = end.as_ref().map(|end| self.expression(end, indent)).unwrap_or_default();
+                    format!("{}[{}:{}]", left, start, end)
+                },
+                Expression::Spread{token:_, value} => {
+                    format!("...{}", self.operand(value, PREFIX, indent))
+                },
+                Expression::Named{token:_, name, value} => {
+                    let name = self.expression(name, indent);
+                    self.lead += name.len() + " = ".len();
+                    format!("{} = {}", name, self.expression(value, indent))
+                },
+                Expression::Propagate{token:_, value} => {
+                    format!("{}?", self.operand(value, CALL, indent))
+                },
+                Expression::MemberExpression{token:_, object, property} => {
+                    format!("{}.{}", self.operand(object, CALL, indent), property)
+                },
+                Expression::PrefixExpression{token:_, operator, right} => {
+                    format!("{}{}", operator, self.operand(right, PREFIX, indent))
+                },
+                Expression::InfixExpression{token, left, operator, right} => {
+                    let precedence = self.precedence(token);
+                    let lead = self.lead;
+                    let left = self.operand(left, precedence, indent);
+                    self.lead = lead + left.len() + operator.len() + 2;
+                    format!("{} {} {}", left, operator, self.operand(right, precedence + 1, indent))
+                },
+                Expression::MatchExpression{token, subject, arms} => self.match_expression(token, subject, arms, indent),
+                Expression::TryExpression{token:_, body, catch, finally} => {
+                    let mut out = format!("try {}", self.block(body, indent));
+                    if let Some(catch) = catch {
+                        out.push_str(" catch ");
+                        if let Some(parameter) = &catch.parameter {
+                            out.push_str(&format!("({}) ", self.pattern(parameter, indent)));
+                        }
+                        out.push_str(&self.block(&catch.body, indent));
+                    }
+                    if let Some(finally) = finally {
+                        out.push_str(&format!(" finally {}", self.block(finally, indent)));
+                    }
+                    out
+                },
+                Expression::IfExpression{token:_, condition, consequence, alternative} => {
+                    let mut out = format!("if ({}) {}", self.expression(condition, indent), self.block(consequence, indent));
+                    if let Some(alternative) = alternative {
+                        out.push_str(&format!(" else {}", self.block(alternative, indent)));
+                    }
+                    out
+                },
+                Expression::FunctionLiteral{token, parameters, body} => {
+                    let open = self.index.get(&token.position()).map(|idx| self.tokens[idx + 1].position()).unwrap_or_default();
+                    let parameters = self.list(open, "(", parameters, ")", indent);
+                    format!("fn{} {}", parameters, self.block(body, indent))
+                },
+                Expression::CallExpression{token, function, arguments} => {
+                    let lead = self.lead;
+                    let function = self.operand(function, CALL, indent);
+                    self.lead = lead + function.len();
+                    format!("{}{}", function, self.list(token.position(), "(", arguments, ")", indent))
+                }
+            }
+        })
+    }
+
+    fn pattern(&mut self, pattern: &Pattern, indent: usize) -> String {
+        with_stack(|| {
+            match pattern {
+                Pattern::Binding(name) | Pattern::Literal(name) => self.expression(name, indent),
+                Pattern::Wildcard(_) => "_".to_string(),
+                Pattern::Array{token, elements, rest} => {
+                    let starts: Vec<Position> = elements.iter().chain(rest.as_deref()).map(pattern_start).collect();
+                    self.wrap(token.position(), "[", "]", &starts, indent, |this, idx, indent| match elements.get(idx) {
+                        Some(element) => this.pattern(element, indent),
+                        None => {
+                            this.lead += "...".len();
+                            format!("...{}", this.pattern(rest.as_ref().unwrap(), indent))
+                        }
+                    })
+                },
+                Pattern::Hash{token, entries} => {
+                    let starts: Vec<Position> = entries.iter().map(|(_, pattern)| pattern_start(pattern)).collect();
+                    self.wrap(token.position(), "{", "}", &starts, indent, |this, idx, indent| {
+                        let (key, pattern) = &entries[idx];
+                        if let Pattern::Binding(Expression::Identifier{token:_, value} | Expression::Local{token:_, value, ..}) = pattern {
+                            if value == key {
+                                return key.clone();
+                            }
+                        }
+                        let key = if is_identifier(key) { key.clone() } else { format!("\"{}\"", key) };
+                        this.lead += key.len() + ": ".len();
+                        format!("{}: {}", key, this.pattern(pattern, indent))
+                    })
+                },
+                Pattern::Variant{token:_, name, value: None} => name.clone(),
+                Pattern::Variant{token:_, name, value: Some(value)} => {
+                    self.lead += name.len() + "(".len();
+                    format!("{}({})", name, self.pattern(value, indent))
+                },
+                Pattern::Or{token:_, alternatives} => {
+                    let alternatives: Vec<String> = alternatives.iter()
+                        .map(|alternative| {
+                            let out = self.pattern(alternative, indent);
+                            self.lead += out.len() + " | ".len();
+                            out
+                        })
+                        .collect();
+                    alternatives.join(" | ")
+                }
+            }
+        })
+    }
+
+    /// Prints a `match` with each arm on its own line. Comments before an
+    /// arm or trailing it stay with that arm.
+    fn match_expression(&mut self, token: &Token, subject: &Expression, arms: &[MatchArm], indent: usize) -> String {
+        let pad = INDENT.repeat(indent + 1);
+        let mut out = format!("match ({}) {{\n", self.expression(subject, indent));
+        self.fitting = false;
+        let end = self.match_end(token);
+        let starts: Vec<Position> = arms.iter().map(|arm| pattern_start(&arm.pattern)).collect();
+        for (idx, arm) in arms.iter().enumerate() {
+            while let Some(comment) = self.take_comment_before(starts[idx]) {
+                out.push_str(&format!("{}{}\n", pad, comment.text));
+            }
+            self.lead = pad.len();
+            let mut head = self.pattern(&arm.pattern, indent + 1);
+            if let Some(guard) = &arm.guard {
+                self.lead = pad.len() + head.len() + " if ".len();
+                head = format!("{} if {}", head, self.expression(guard, indent + 1));
+            }
+            self.lead = pad.len() + head.len() + " => ".len();
+            match &*arm.body {
+                Statement::ExpressionStatement{token:_, expression} => {
+                    let body = self.expression(expression, indent + 1);
+                    out.push_str(&format!("{}{} => {},", pad, head, body));
+                },
+                body => {
+                    let body = self.block(body, indent + 1);
+                    out.push_str(&format!("{}{} => {}", pad, head, body));
+                }
+            }
+            let next = starts.get(idx + 1).cloned().unwrap_or(end);
+            if let Some(comment) = self.take_comment_on_line(self.line_before(next), next) {
+                out.push_str(&format!(" {}", comment.text));
+            }
+            out.push('\n');
+        }
+        while let Some(comment) = self.take_comment_before(end) {
+            out.push_str(&format!("{}{}\n", pad, comment.text));
+        }
+        out.push_str(&INDENT.repeat(indent));
+        out.push('}');
+        out
+    }
+
+    /// Position of the closing brace of the `match` at `token`, which
+    /// follows the parenthesised subject.
+    fn match_end(&self, token: &Token) -> Position {
+        let after = |position: Position| self.index.get(&position)
+            .and_then(|idx| self.tokens.get(idx + 1))
+            .map(|token| token.position());
+        after(token.position())
+            .and_then(|paren| self.closing.get(&paren).cloned())
+            .and_then(after)
+            .and_then(|brace| self.closing.get(&brace).cloned())
+            .unwrap_or((usize::MAX, 0))
+    }
+
+    /// Prints a sub-expression, adding parentheses when its own precedence is
+    /// lower than what the surrounding operator binds with.
+    fn operand(&mut self, expression: &Expression, min_precedence: u32, indent: usize) -> String {
+        let out = self.expression(expression, indent);
+        let precedence = match expression {
+            Expression::InfixExpression{token, ..} => self.precedence(token),
+            Expression::PrefixExpression{..} => PREFIX,
+            Expression::CallExpression{..} => CALL,
+            _ => INDEX
+        };
+        if precedence < min_precedence {
+            return format!("({})", out);
+        }
+        out
+    }
+
+    fn precedence(&self, token: &Token) -> u32 {
+        self.precedences.get(&token.token_type).cloned().unwrap_or(LOWEST)
+    }
+
+    fn list(&mut self, open_at: Position, open: &str, items: &[Expression], close: &str, indent: usize) -> String {
+        let starts: Vec<Position> = items.iter().map(expression_start).collect();
+        self.wrap(open_at, open, close, &starts, indent, |this, idx, indent| this.expression(&items[idx], indent))
+    }
+
+    /// Keeps a list on one line when it fits, otherwise puts every item on
+    /// its own line. A multi-line last item (usually a function literal) may
+    /// still hug the opening line. `open_at` is the position of the opening
+    /// token and `starts` that of each item, so a list with comments inside
+    /// is always broken and keeps each comment next to its item.
+    fn wrap<F>(&mut self, open_at: Position, open: &str, close: &str, starts: &[Position], indent: usize, render: F) -> String
+        where F: Fn(&mut Self, usize, usize) -> String {
+        let lead = self.lead;
+        let fitting = std::mem::replace(&mut self.fitting, false);
+        let end = self.closing.get(&open_at).cloned().unwrap_or((usize::MAX, 0));
+        let has_comment = self.comments.get(self.next_comment).is_some_and(|comment| comment.position() < end);
+        if !has_comment && lead + open.len() <= MAX_WIDTH {
+            let mut column = lead + open.len();
+            let mut flat = Vec::new();
+            // Stop as soon as the line cannot fit, so nested lists are not
+            // rendered both ways at every level.
+            for idx in 0..starts.len() {
+                if column > MAX_WIDTH || flat.iter().any(|item: &String| item.contains('\n')) {
+                    break;
+                }
+                self.lead = column;
+                self.fitting = true;
+                let item = render(self, idx, indent);
+                self.fitting = false;
+                column += item.len() + ", ".len();
+                flat.push(item);
+            }
+            let line = format!("{}{}{}", open, flat.join(", "), close);
+            let first_line = line.lines().next().unwrap_or("");
+            let hugs = flat.iter().rev().skip(1).all(|item| !item.contains('\n'));
+            if flat.len() == starts.len() && hugs && lead + first_line.len() <= MAX_WIDTH {
+                return line;
+            }
+        }
+        let mut out = format!("{}\n", open);
+        if fitting && lead + open.len() > MAX_WIDTH {
+            return out;
+        }
+        let pad = INDENT.repeat(indent + 1);
+        for idx in 0..starts.len() {
+            while let Some(comment) = self.take_comment_before(starts[idx]) {
+                out.push_str(&format!("{}{}\n", pad, comment.text));
+            }
+            self.lead = pad.len();
+            let item = render(self, idx, indent + 1);
+            let sep = if idx + 1 < starts.len() { "," } else { "" };
+            out.push_str(&format!("{}{}{}", pad, item, sep));
+            let next = starts.get(idx + 1).cloned().unwrap_or(end);
+            if let Some(comment) = self.take_comment_on_line(self.line_before(next), next) {
+                out.push_str(&format!(" {}", comment.text));
+            }
+            out.push('\n');
+        }
+        while let Some(comment) = self.take_comment_before(end) {
+            out.push_str(&format!("{}{}\n", pad, comment.text));
+        }
+        out.push_str(&INDENT.repeat(indent));
+        out.push_str(close);
+        out
+    }
+}
+
+/// Position of the first token of an expression, which for operators and
+/// calls is not the expression's own token.
+fn expression_start(expression: &Expression) -> Position {
+    match expression {
+        Expression::InfixExpression{token:_, left, ..}
+        | Expression::IndexExpression{token:_, left, ..}
+        | Expression::SliceExpression{token:_, left, ..} => expression_start(left),
+        Expression::CallExpression{token:_, function, ..} => expression_start(function),
+        Expression::MemberExpression{token:_, object, ..} => expression_start(object),
+        Expression::Propagate{token:_, value} => expression_start(value),
+        Expression::Named{token:_, name, ..} => expression_start(name),
+        Expression::Nope => (0, 0),
+        Expression::Identifier{token, ..}
+        | Expression::Local{token, ..}
+        | Expression::IntegerLiteral{token, ..}
+        | Expression::StringLiteral{token, ..}
+        | Expression::TemplateLiteral{token, ..}
+        | Expression::ArrayLiteral{token, ..}
+        | Expression::HashLiteral{token, ..}
+        | Expression::Spread{token, ..}
+        | Expression::PrefixExpression{token, ..}
+        | Expression::Boolean{token, ..}
+        | Expression::IfExpression{token, ..}
+        | Expression::FunctionLiteral{token, ..}
+        | Expression::MatchExpression{token, ..}
+        | Expression::TryExpression{token, ..} => token.position()
+    }
+}
+
+fn pattern_start(pattern: &Pattern) -> Position {
+    match pattern {
+        Pattern::Binding(expression) | Pattern::Literal(expression) => expression_start(expression),
+        Pattern::Wildcard(token) | Pattern::Array{token, ..} | Pattern::Hash{token, ..} | Pattern::Variant{token, ..} => token.position(),
+        Pattern::Or{token, alternatives} => alternatives.first().map(pattern_start).unwrap_or_else(|| token.position())
+    }
+}
+
+fn push_blank_line(out: &mut String, last_line: Option<usize>, line: usize) {
+    if let Some(last_line) = last_line {
+        if line > last_line + 1 {
+            out.push('\n');
+        }
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    name.chars().next().is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && name.chars().all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_source;
+
+    #[test]
+    fn keeps_comments_inside_lists_in_place() {
+        let source = "let h = {\n // the first key\n \"a\": 1, // one\n \"b\": 2\n};\nputs(1, // first\n2);\n";
+        let expected = "let h = {\n    // the first key\n    \"a\": 1, // one\n    \"b\": 2\n};\nputs(\n    1, // first\n    2\n);\n";
+        assert_eq!(format_source(source).unwrap(), expected);
+        assert_eq!(format_source(expected).unwrap(), expected);
+    }
+
+    #[test]
+    fn keeps_comments_inside_match_arms_in_place() {
+        let source = "match (x) {\n// zero\n0 => \"none\", // nothing\n// one\n1 => { \"one\" } // block\n_ => \"many\"\n// done\n}\n";
+        let expected = "match (x) {\n    // zero\n    0 => \"none\", // nothing\n    // one\n    1 => {\n        \"one\"\n    } // block\n    _ => \"many\",\n    // done\n}\n";
+        assert_eq!(format_source(source).unwrap(), expected);
+        assert_eq!(format_source(expected).unwrap(), expected);
+    }
+
+    #[test]
+    fn keeps_short_lists_flat() {
+        let source = "let xs = [1,2,\n3];\nlet f = fn(a,b) { a + b };\n";
+        assert_eq!(format_source(source).unwrap(), "let xs = [1, 2, 3];\nlet f = fn(a, b) {\n    a + b\n};\n");
+    }
+
+    #[test]
+    fn keeps_escaped_interpolations_escaped() {
+        let source = "let s = \"\\${a} ${ a+1 } \\${b}\";\nlet t = \"\\${plain}\";\n";
+        let expected = "let s = \"\\${a} ${a + 1} \\${b}\";\nlet t = \"\\${plain}\";\n";
+        assert_eq!(format_source(source).unwrap(), expected);
+        assert_eq!(format_source(expected).unwrap(), expected);
+    }
+
+    #[test]
+    fn formats_deeply_nested_code() {
+        let source = format!("{}1{}", "(".repeat(10_000), ")".repeat(10_000));
+        assert_eq!(format_source(&source).unwrap(), "1;\n");
+        let source = format!("{}1{}", "[".repeat(1_000), "]".repeat(1_000));
+        let formatted = format_source(&source).unwrap();
+        assert_eq!(format_source(&formatted).unwrap(), formatted);
+    }
+}
@@ -0,0 +1,219 @@
+use std::{cell::RefCell, fmt, fs, path::Path, rc::Rc};
+
+use crate::builtins::Builtins;
+use crate::convert::{Callable, IntoArgs};
+use crate::environment::Environment;
+use crate::limits::{self, Limits};
+use crate::evaluator::Evaluator;
+use crate::lexer::Lexer;
+use crate::object::{Object, EXIT_ERROR};
+use crate::optimizer::ConstantFolder;
+use crate::parser::Parser;
+use crate::resolver::Resolver;
+use crate::visitor::Fold;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    Parse(String),
+    Resolve(Vec<String>),
+    Runtime(String),
+    Io(String),
+    /// The script called `exit(code)`.
+    Exit(i32)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Parse(message) => write!(f, "parse error: {}", message),
+            Error::Resolve(messages) => write!(f, "{}", messages.join("\n")),
+            Error::Runtime(message) => write!(f, "runtime error: {}", message),
+            Error::Io(message) => write!(f, "{}", message),
+            Error::Exit(code) => write!(f, "exited with code {}", code)
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A Monkey interpreter with its own global scope.
+///
+/// Globals persist across calls, so code evaluated later can use the
+/// functions and values defined earlier, like in the REPL:
+///
+/// ```
+/// use monkey::{Interpreter, object::Object};
+///
+/// let mut interpreter = Interpreter::new();
+/// interpreter.set_global("base", Object::new_int(40));
+/// interpreter.eval_str("let add = fn(x) { base + x };").unwrap();
+/// let result = interpreter.call_function("add", vec![Object::new_int(2)]).unwrap();
+/// assert_eq!(result, Object::new_int(42));
+/// ```
+pub struct Interpreter {
+    environment: Rc<RefCell<Environment>>,
+    limits: Limits
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Interpreter::with_builtins(Builtins::new())
+    }
+
+    /// Creates an interpreter whose root scope is `builtins`, e.g. after
+    /// adding host functions with `Builtins::register_fn`.
+    pub fn with_builtins(builtins: Builtins) -> Self {
+        Interpreter {
+            environment: Rc::new(RefCell::new(builtins.builtins.create_child())),
+            limits: Limits::default()
+        }
+    }
+
+    pub fn environment(&self) -> Rc<RefCell<Environment>> {
+        self.environment.clone()
+    }
+
+    /// Sets the budgets that apply to each later `eval_str` and `call`.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    pub fn eval_str(&mut self, source: &str) -> Result<Object, Error> {
+        let mut parser = Parser::new(Lexer::new(source.to_string()));
+        let program = parser.parse_program().map_err(Error::Parse)?;
+        let program = ConstantFolder.fold_program(program);
+        let program = Resolver::new(&self.environment.borrow()).resolve(program).map_err(Error::Resolve)?;
+        let _budget = limits::start(self.limits);
+        into_result(program.evaluate(self.environment.clone()))
+    }
+
+    pub fn eval_file<P: AsRef<Path>>(&mut self, path: P) -> Result<Object, Error> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path)
+            .map_err(|err| Error::Io(format!("{}: {}", path.display(), err)))?;
+        self.eval_str(&source)
+    }
+
+    pub fn set_global(&mut self, name: &str, value: Object) {
+        self.environment.borrow_mut().set(name.to_string(), value);
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Object> {
+        self.environment.borrow().get(name)
+    }
+
+    /// Calls the global function or builtin called `name`.
+    pub fn call_function<A: IntoArgs>(&mut self, name: &str, args: A) -> Result<Object, Error> {
+        let function = self.get_global(name)
+            .ok_or_else(|| Error::Runtime(format!("identifier not found: {}", name)))?;
+        self.call(function, args)
+    }
+
+    /// Calls a function or builtin value, e.g. a callback a script handed
+    /// to the host earlier.
+    pub fn call<A: IntoArgs>(&mut self, function: Object, args: A) -> Result<Object, Error> {
+        let function = self.callable(function)?;
+        let _budget = limits::start(self.limits);
+        into_result(function.call_object(args))
+    }
+
+    /// Wraps a function or builtin value for the host to keep and call
+    /// later, under this interpreter's limits as they are now.
+    pub fn callable(&self, function: Object) -> Result<Callable, Error> {
+        Callable::with_limits(function, self.limits).map_err(Error::Runtime)
+    }
+}
+
+fn into_result(mut object: Object) -> Result<Object, Error> {
+    match &mut object {
+        Object::Error{message, kind, ..} if kind == EXIT_ERROR => Err(Error::Exit(message.parse().unwrap_or(1))),
+        Object::Error{message, ..} => Err(Error::Runtime(std::mem::take(message))),
+        _ => Ok(object)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::{Error, Interpreter};
+    use crate::builtins::Builtins;
+    use crate::convert::{Callable, FromObject};
+    use crate::limits::Limits;
+    use crate::object::Object;
+
+    #[test]
+    fn results_compare_without_panicking() {
+        let mut interpreter = Interpreter::new();
+        assert_eq!(interpreter.eval_str("if (false) { 1 }"), Ok(Object::Null));
+        assert_eq!(interpreter.eval_str("let h = {1: \"a\", 2: \"b\"}; h"), interpreter.eval_str("let h = {2: \"b\", 1: \"a\"}; h"));
+        assert_ne!(interpreter.eval_str("let h = {1: \"a\"}; h"), interpreter.eval_str("let h = {1: \"b\"}; h"));
+
+        interpreter.eval_str("let f = fn(x) { x }; let g = fn(x) { x };").unwrap();
+        assert_eq!(interpreter.get_global("f"), interpreter.get_global("f"));
+        assert_ne!(interpreter.get_global("f"), interpreter.get_global("g"));
+        assert_eq!(interpreter.get_global("len"), interpreter.get_global("len"));
+        assert_ne!(interpreter.get_global("len"), interpreter.get_global("puts"));
+        assert_ne!(interpreter.get_global("f"), Some(Object::Null));
+    }
+
+    #[test]
+    fn callables_keep_the_limits() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_limits(Limits { max_steps: Some(1000), ..Limits::default() });
+        let f = interpreter.eval_str("let count = fn(n) { if (n == 0) { \"done\" } else { count(n - 1) } }; fn() { count(100000) }").unwrap();
+        let exceeded = "limit exceeded: more than 1000 steps".to_string();
+        assert_eq!(interpreter.call(f.clone(), ()), Err(Error::Runtime(exceeded.clone())));
+        assert_eq!(interpreter.callable(f.clone()).unwrap().call(()), Err(exceeded.clone()));
+        assert!(Callable::from_object(f).unwrap_err().contains("no limits to run under"));
+
+        // A callback a script hands to a builtin keeps the limits in force then.
+        let kept = Rc::new(RefCell::new(None));
+        let keep = kept.clone();
+        let mut builtins = Builtins::new();
+        builtins.register_fn("keep", move |f: Callable| *keep.borrow_mut() = Some(f));
+        let mut interpreter = Interpreter::with_builtins(builtins);
+        interpreter.set_limits(Limits { max_steps: Some(1000), ..Limits::default() });
+        interpreter.eval_str("let count = fn(n) { if (n == 0) { \"done\" } else { count(n - 1) } }; keep(fn() { count(100000) })").unwrap();
+        let callback = kept.borrow_mut().take().unwrap();
+        assert_eq!(callback.call(()), Err(exceeded));
+    }
+
+    #[test]
+    fn calls_functions_and_builtins_from_rust() {
+        let mut interpreter = Interpreter::new();
+        let add = interpreter.eval_str("fn(a, b = 10) { a + b }").unwrap();
+        assert_eq!(interpreter.call(add.clone(), (1, 2)), Ok(Object::new_int(3)));
+        assert_eq!(interpreter.call(add.clone(), (1,)), Ok(Object::new_int(11)));
+        assert_eq!(interpreter.call(add.clone(), ()), Err(Error::Runtime("wrong number of arguments: want=1 to 2, got=0".to_string())));
+        assert_eq!(interpreter.call_function("len", ("abc",)), Ok(Object::new_int(3)));
+
+        let add = interpreter.callable(add).unwrap();
+        assert_eq!(add.call_as::<i64, _>((1, 2)), Ok(3));
+        assert_eq!(add.call_as::<String, _>((1, 2)), Err("expected STRING, got INTEGER".to_string()));
+        let fail = interpreter.eval_str("fn() { throw \"boom\" }").unwrap();
+        assert_eq!(interpreter.callable(fail).unwrap().call(()), Err("boom".to_string()));
+    }
+
+    #[test]
+    fn only_functions_are_callable() {
+        let mut interpreter = Interpreter::new();
+        let not_callable = Err(Error::Runtime("expected FUNCTION, got INTEGER".to_string()));
+        assert_eq!(interpreter.call(Object::new_int(1), ()), not_callable);
+        assert_eq!(interpreter.callable(Object::new_int(1)).unwrap_err(), Error::Runtime("expected FUNCTION, got INTEGER".to_string()));
+        assert_eq!(Callable::from_object(Object::new_string("f".to_string())).unwrap_err(), "expected FUNCTION, got STRING");
+        assert_eq!(interpreter.call_function("missing", ()), Err(Error::Runtime("identifier not found: missing".to_string())));
+        interpreter.eval_str("let x = 1;").unwrap();
+        assert_eq!(interpreter.call_function("x", ()), not_callable);
+    }
+}
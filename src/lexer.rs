@@ -7,6 +7,12 @@ pub enum TokenType {
     IDENT,
     INT,
     STRING,
+    /// The pieces of a string with `${...}` in it: the text up to the
+    /// first `${`, the text between a `}` and the next `${`, and the text
+    /// from the last `}` to the closing quote.
+    TEMPLATESTART,
+    TEMPLATEMIDDLE,
+    TEMPLATEEND,
 
     ASSIGN,
     PLUS,
@@ -20,10 +26,17 @@ pub enum TokenType {
     GT,
     LTE,
     GTE,
+    AND,
+    OR,
 
     COMA,
+    DOT,
+    ELLIPSIS,
     SEMICOLON,
     COLON,
+    PIPE,
+    ARROW,
+    QUESTION,
 
     LPAREN,
     RPAREN,
@@ -39,7 +52,12 @@ pub enum TokenType {
     FALSE,
     IF,
     ELSE,
-    RETURN
+    RETURN,
+    MATCH,
+    THROW,
+    TRY,
+    CATCH,
+    FINALLY
 }
 
 impl From<&str> for TokenType {
@@ -67,16 +85,48 @@ impl From<&str> for TokenType {
 #[derive(Debug, Clone, PartialEq, Hash, Eq)]
 pub struct Token {
     pub token_type: TokenType,
-    pub literal: String
+    pub literal: String,
+    pub line: usize,
+    pub column: usize,
+    /// Where the token's text starts and ends in the source, as byte
+    /// offsets. For a string this includes the quotes, so tools can print
+    /// it back the way it was written.
+    pub span: (usize, usize)
 }
 
 impl Token {
     pub fn new(token_type: TokenType, ch: char) -> Self {
+        Token::with_literal(token_type, ch.to_string())
+    }
+
+    pub fn with_literal(token_type: TokenType, literal: String) -> Self {
         Token{
             token_type,
-            literal: ch.to_string()
+            literal,
+            line: 0,
+            column: 0,
+            span: (0, 0)
         }
     }
+
+    pub fn position(&self) -> (usize, usize) {
+        (self.line, self.column)
+    }
+}
+
+/// A `//` line comment. The lexer skips comments when producing tokens but
+/// keeps them around so tools like the formatter can put them back.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    pub text: String,
+    pub line: usize,
+    pub column: usize
+}
+
+impl Comment {
+    pub fn position(&self) -> (usize, usize) {
+        (self.line, self.column)
+    }
 }
 
 #[derive(Debug)]
@@ -85,7 +135,23 @@ pub struct Lexer {
     position: usize,
     read_position: usize,
     ch: char,
-    keyworks: HashMap<String, TokenType>
+    line: usize,
+    column: usize,
+    comments: Vec<Comment>,
+    // Problems such as an unterminated string, reported by the parser.
+    errors: Vec<String>,
+    keyworks: HashMap<String, TokenType>,
+    // One entry per `${` still open, counting the braces opened inside
+    // it, so the `}` that ends it can be told apart.
+    templates: Vec<Template>
+}
+
+/// A `${` still open, and where the string part before it started.
+#[derive(Debug)]
+struct Template {
+    braces: usize,
+    line: usize,
+    column: usize
 }
 
 
@@ -96,7 +162,12 @@ impl Lexer {
             position: 0,
             read_position: 0,
             ch: '\0',
-            keyworks: HashMap::new()
+            line: 1,
+            column: 0,
+            comments: Vec::new(),
+            errors: Vec::new(),
+            keyworks: HashMap::new(),
+            templates: Vec::new()
         };
         l.add_keywords();
         l.read_char();
@@ -111,24 +182,52 @@ impl Lexer {
         self.keyworks.insert("if".to_string(), TokenType::IF);
         self.keyworks.insert("else".to_string(), TokenType::ELSE);
         self.keyworks.insert("return".to_string(), TokenType::RETURN);
+        self.keyworks.insert("match".to_string(), TokenType::MATCH);
+        self.keyworks.insert("throw".to_string(), TokenType::THROW);
+        self.keyworks.insert("try".to_string(), TokenType::TRY);
+        self.keyworks.insert("catch".to_string(), TokenType::CATCH);
+        self.keyworks.insert("finally".to_string(), TokenType::FINALLY);
 
     }
 
     fn lookup_ident(&self, ident: &String) -> TokenType {
         match self.keyworks.get(ident) {
-            Some(token_type) => token_type.clone(),
+            Some(token_type) => *token_type,
             None => TokenType::IDENT
         }
     }
 
+    pub fn comments(&self) -> &[Comment] {
+        &self.comments
+    }
+
+    /// Errors found so far, as `line:column: message`.
+    pub fn errors(&self) -> &[String] {
+        &self.errors
+    }
+
+    fn unterminated_string(&mut self, line: usize, column: usize) {
+        self.errors.push(format!("{}:{}: unterminated string", line, column));
+    }
+
     pub fn read_char(&mut self) {
-        if self.read_position >= self.input.len() {
-            self.ch = '\0';
+        if self.ch == '\n' {
+            self.line += 1;
+            self.column = 1;
         } else {
-            self.ch = self.input.chars().nth(self.read_position).unwrap();
+            self.column += 1;
         }
         self.position = self.read_position;
-        self.read_position += 1;
+        match self.input.get(self.read_position..).and_then(|rest| rest.chars().next()) {
+            Some(ch) => {
+                self.ch = ch;
+                self.read_position += ch.len_utf8();
+            },
+            None => {
+                self.ch = '\0';
+                self.read_position += 1;
+            }
+        }
     }
 
     fn skip_whitespace(&mut self) {
@@ -137,25 +236,43 @@ impl Lexer {
         }
     }
 
-    fn peek_char(&self) -> char {
-        if self.read_position >= self.input.len() {
-            '\0'
-        } else {
-            self.input.chars().nth(self.read_position).unwrap()
+    fn skip_whitespace_and_comments(&mut self) {
+        self.skip_whitespace();
+        while self.ch == '/' && self.peek_char() == '/' {
+            self.read_comment();
+            self.skip_whitespace();
         }
     }
 
+    fn read_comment(&mut self) {
+        let start = self.position;
+        let (line, column) = (self.line, self.column);
+        while self.peek_char() != '\n' && self.peek_char() != '\0' {
+            self.read_char();
+        }
+        self.comments.push(Comment {
+            text: self.input[start..self.read_position].trim_end().to_string(),
+            line,
+            column
+        });
+        self.read_char();
+    }
+
+    fn peek_char(&self) -> char {
+        self.input.get(self.read_position..).and_then(|rest| rest.chars().next()).unwrap_or('\0')
+    }
+
     pub fn next_token(&mut self) -> Token {
-        let token: Token;
-        self.skip_whitespace();
-        token = match self.ch {
+        self.skip_whitespace_and_comments();
+        let (line, column, start) = (self.line, self.column, self.position);
+        let mut token = match self.ch {
             '=' => {
                 if self.peek_char() == '=' {
                     self.read_char();
-                    Token{
-                        token_type: TokenType::EQ,
-                        literal: "==".to_string()
-                    }   
+                    Token::with_literal(TokenType::EQ, "==".to_string())   
+                } else if self.peek_char() == '>' {
+                    self.read_char();
+                    Token::with_literal(TokenType::ARROW, "=>".to_string())
                 } else {
                     Token::new(TokenType::ASSIGN, self.ch)
                 }
@@ -163,10 +280,7 @@ impl Lexer {
             '!' => {
                 if self.peek_char() == '=' {
                     self.read_char();
-                    Token {
-                        token_type: TokenType::NOTEQ,
-                        literal: "!=".to_string()
-                    }
+                    Token::with_literal(TokenType::NOTEQ, "!=".to_string())
                 } else {
                     Token::new(TokenType::BANG, self.ch)
                 }
@@ -174,10 +288,7 @@ impl Lexer {
             '>' => {
                 if self.peek_char() == '=' {
                     self.read_char();
-                    Token {
-                        token_type: TokenType::GTE,
-                        literal: ">=".to_string()
-                    }
+                    Token::with_literal(TokenType::GTE, ">=".to_string())
                 } else {
                     Token::new(TokenType::GT, self.ch)
                 }
@@ -185,56 +296,102 @@ impl Lexer {
             '<' => {
                 if self.peek_char() == '=' {
                     self.read_char();
-                    Token {
-                        token_type: TokenType::LTE,
-                        literal: "<=".to_string()
-                    }
+                    Token::with_literal(TokenType::LTE, "<=".to_string())
                 } else {
                     Token::new(TokenType::LT, self.ch)
                 }
             },
+            '&' => {
+                if self.peek_char() == '&' {
+                    self.read_char();
+                    Token::with_literal(TokenType::AND, "&&".to_string())
+                } else {
+                    Token::new(TokenType::ILLEGAL, self.ch)
+                }
+            },
+            '|' => {
+                if self.peek_char() == '|' {
+                    self.read_char();
+                    Token::with_literal(TokenType::OR, "||".to_string())
+                } else {
+                    Token::new(TokenType::PIPE, self.ch)
+                }
+            },
             ';' => Token::new(TokenType::SEMICOLON, self.ch),
+            '?' => Token::new(TokenType::QUESTION, self.ch),
             ':' => Token::new(TokenType::COLON, self.ch),
             '(' => Token::new(TokenType::LPAREN, self.ch),
             ')' => Token::new(TokenType::RPAREN, self.ch),
-            '{' => Token::new(TokenType::LBRACE, self.ch),
-            '}' => Token::new(TokenType::RBRACE, self.ch),
+            '{' => {
+                if let Some(template) = self.templates.last_mut() {
+                    template.braces += 1;
+                }
+                Token::new(TokenType::LBRACE, self.ch)
+            },
+            '}' => match self.templates.last_mut().map(|template| &mut template.braces) {
+                Some(0) => {
+                    self.templates.pop();
+                    let (text, open) = self.read_string_part();
+                    let token_type = if open { TokenType::TEMPLATEMIDDLE } else { TokenType::TEMPLATEEND };
+                    Token::with_literal(token_type, text)
+                },
+                Some(depth) => {
+                    *depth -= 1;
+                    Token::new(TokenType::RBRACE, self.ch)
+                },
+                None => Token::new(TokenType::RBRACE, self.ch)
+            },
             '+' => Token::new(TokenType::PLUS, self.ch),
             '-' => Token::new(TokenType::MINUS, self.ch),
             '*' => Token::new(TokenType::ASTERISK, self.ch),
             '/' => Token::new(TokenType::SLASH, self.ch),
             ',' => Token::new(TokenType::COMA, self.ch),
+            '.' => {
+                if self.input[self.read_position..].starts_with("..") {
+                    self.read_char();
+                    self.read_char();
+                    Token::with_literal(TokenType::ELLIPSIS, "...".to_string())
+                } else {
+                    Token::new(TokenType::DOT, self.ch)
+                }
+            },
             '[' => Token::new(TokenType::LBRACKET, self.ch),
             ']' => Token::new(TokenType::RBRACKET, self.ch),
-            '\0' => Token::new(TokenType::EOF, '\0'),
-            '"' => {
-                let s = self.read_string();
-                Token {
-                    token_type: TokenType::STRING,
-                    literal: s
+            '\0' => {
+                if let Some(template) = self.templates.pop() {
+                    self.templates.clear();
+                    self.unterminated_string(template.line, template.column);
                 }
+                Token::new(TokenType::EOF, '\0')
+            },
+            '"' if self.input[self.read_position..].starts_with("\"\"") => {
+                Token::with_literal(TokenType::STRING, self.read_triple_quoted())
+            },
+            'r' if self.raw_string_hashes().is_some() => {
+                Token::with_literal(TokenType::STRING, self.read_raw_string())
+            },
+            '"' => {
+                let (text, open) = self.read_string_part();
+                let token_type = if open { TokenType::TEMPLATESTART } else { TokenType::STRING };
+                Token::with_literal(token_type, text)
             }
             _ => {
                 if self.ch.is_ascii_digit() {
                     let number = self.read_number();
-                    Token {
-                        token_type: TokenType::INT,
-                        literal: number 
-                    }
-                } else if self.ch.is_ascii_alphabetic() {
+                    Token::with_literal(TokenType::INT, number)
+                } else if self.ch.is_ascii_alphabetic() || self.ch == '_' {
                     let identifier = self.read_identifier();
-                    Token {
-                        token_type: self.lookup_ident(&identifier),
-                        literal: identifier
-                    }
+                    Token::with_literal(self.lookup_ident(&identifier), identifier)
                 } else {
                     Token::new(TokenType::ILLEGAL, self.ch)
                 }
             }
         };
+        token.line = line;
+        token.column = column;
+        token.span = (start, self.read_position.min(self.input.len()));
         self.read_char();
         token
-
     }
 
     fn read_number(&mut self) -> String {
@@ -247,20 +404,167 @@ impl Lexer {
 
     fn read_identifier(&mut self) -> String {
         let start = self.position;
-        while self.peek_char().is_alphanumeric() || self.ch == '_' {
+        while self.peek_char().is_alphanumeric() || self.peek_char() == '_' {
             self.read_char();
         }
         self.input[start..self.read_position].to_string()
     }
 
-    fn read_string(&mut self) -> String {
-        let position = self.position + 1;
+    /// Reads string text up to the closing quote or the next `${`, which
+    /// opens an interpolation. Says which one it stopped at. `\${` is a
+    /// literal `${`.
+    fn read_string_part(&mut self) -> (String, bool) {
+        let (line, column) = (self.line, self.column);
+        let mut text = String::new();
+        let mut position = self.position + 1;
         loop {
             self.read_char();
-            if self.ch == '"' || self.ch == '\0' {
+            if self.ch == '\\' && self.input[self.read_position..].starts_with("${") {
+                text.push_str(&self.input[position..self.position]);
+                self.read_char();
+                self.read_char();
+                position = self.position - 1;
+                continue;
+            }
+            if self.ch == '$' && self.peek_char() == '{' {
+                text.push_str(&self.input[position..self.position]);
+                self.read_char();
+                self.templates.push(Template{braces: 0, line, column});
+                return (text, true);
+            }
+            if self.ch == '"' {
+                break;
+            }
+            if self.ch == '\0' {
+                self.unterminated_string(line, column);
                 break;
             }
         }
-        self.input[position..self.position].to_string()
+        text.push_str(&self.input[position..self.position.min(self.input.len())]);
+        (text, false)
+    }
+
+    /// `"""` text `"""`, which can span lines. A line break right after
+    /// the opening quotes and the whitespace-only line before the closing
+    /// ones are dropped, as is the indentation all remaining lines share.
+    fn read_triple_quoted(&mut self) -> String {
+        let (line, column) = (self.line, self.column);
+        self.read_char();
+        self.read_char();
+        let start = self.read_position;
+        while !(self.peek_char() == '\0' || self.input[self.read_position..].starts_with("\"\"\"")) {
+            self.read_char();
+        }
+        if self.peek_char() == '\0' {
+            self.unterminated_string(line, column);
+        }
+        let text = self.input[start..self.read_position].to_string();
+        self.read_char();
+        self.read_char();
+        self.read_char();
+        dedent(&text)
+    }
+
+    /// The number of `#` in the `r#"` that starts a raw string here, if
+    /// one does.
+    fn raw_string_hashes(&self) -> Option<usize> {
+        let rest = &self.input[self.read_position..];
+        let hashes = rest.len() - rest.trim_start_matches('#').len();
+        if rest[hashes..].starts_with('"') {
+            Some(hashes)
+        } else {
+            None
+        }
     }
-}
\ No newline at end of file
+
+    /// `r"text"` or `r#"text"#`: no interpolation, and with `#`s the text
+    /// can hold quotes, up to a quote followed by as many `#`s.
+    fn read_raw_string(&mut self) -> String {
+        let (line, column) = (self.line, self.column);
+        let hashes = self.raw_string_hashes().unwrap_or(0);
+        let close = format!("\"{}", "#".repeat(hashes));
+        for _ in 0..=hashes {
+            self.read_char();
+        }
+        let start = self.read_position;
+        while !(self.peek_char() == '\0' || self.input[self.read_position..].starts_with(&close)) {
+            self.read_char();
+        }
+        if self.peek_char() == '\0' {
+            self.unterminated_string(line, column);
+        }
+        let text = self.input[start..self.read_position].to_string();
+        for _ in 0..close.len() {
+            self.read_char();
+        }
+        text
+    }
+}
+
+fn dedent(text: &str) -> String {
+    // Only ASCII spaces and tabs count as indentation.
+    let indentation = |line: &str| line.len() - line.trim_start_matches([' ', '\t']).len();
+    let is_blank = |line: &str| indentation(line) == line.len();
+    let text = text.strip_prefix("\r\n").or_else(|| text.strip_prefix('\n')).unwrap_or(text);
+    let mut lines: Vec<&str> = text.split('\n').map(|line| line.trim_end_matches('\r')).collect();
+    if lines.len() > 1 && lines.last().is_some_and(|line| is_blank(line)) {
+        lines.pop();
+    }
+    let prefix = lines.iter()
+        .filter(|line| !is_blank(line))
+        .map(|line| &line[..indentation(line)])
+        .reduce(|prefix, indent| {
+            let common = prefix.bytes().zip(indent.bytes()).take_while(|(a, b)| a == b).count();
+            &prefix[..common]
+        })
+        .unwrap_or("");
+    lines.iter()
+        .map(|line| if is_blank(line) { "" } else { &line[prefix.len()..] })
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dedent, Lexer, TokenType};
+    use crate::parser::Parser;
+
+    #[test]
+    fn dedents_by_the_common_ascii_indentation() {
+        assert_eq!(dedent("\n    a\n      b\n    "), "a\n  b");
+        assert_eq!(dedent("\n\u{3000}x\n y\n"), "\u{3000}x\n y");
+        assert_eq!(dedent("\n\t\tx\n\t\t  y\n\n\t\t"), "x\n  y\n");
+    }
+
+    #[test]
+    fn reports_unterminated_strings() {
+        for (source, error) in [
+            ("let x = \"abc", "1:9: unterminated string"),
+            ("let x = \"\"\"abc", "1:9: unterminated string"),
+            ("let x = r#\"abc\"", "1:9: unterminated string"),
+            ("puts(1);\nlet x = \"a${1}b", "2:14: unterminated string"),
+            ("let x = \"a ${1 + (2", "1:9: unterminated string"),
+            ("let x = \"a ${", "1:9: unterminated string"),
+            ("let x = \"a ${1} ${ {", "1:15: unterminated string"),
+            ("let x = \"${\"a ${1\"}", "1:18: unterminated string")
+        ] {
+            let mut parser = Parser::new(Lexer::new(source.to_string()));
+            assert_eq!(parser.parse_program().unwrap_err(), error, "{}", source);
+        }
+    }
+
+    #[test]
+    fn spans_cover_the_token_source() {
+        let source = "let é = r#\"a\"b\"#;";
+        let mut lexer = Lexer::new(source.to_string());
+        let mut spans = Vec::new();
+        loop {
+            let token = lexer.next_token();
+            if token.token_type == TokenType::EOF {
+                break;
+            }
+            spans.push(&source[token.span.0..token.span.1]);
+        }
+        assert_eq!(spans, ["let", "é", "=", "r#\"a\"b\"#", ";"]);
+    }
+}
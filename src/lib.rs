@@ -0,0 +1,24 @@
+// Hash keys are checked with `Object::is_hashable` before they are stored
+// or looked up, so no key holds a function and its mutable environment.
+#![allow(clippy::mutable_key_type)]
+
+pub mod lexer;
+pub mod ast;
+pub mod parser;
+pub mod object;
+pub mod evaluator;
+pub mod environment;
+pub mod builtins;
+pub mod convert;
+pub mod native;
+pub mod limits;
+#[cfg(feature = "serde")]
+pub mod serialization;
+pub mod formatter;
+pub mod visitor;
+pub mod optimizer;
+pub mod resolver;
+pub mod linter;
+pub mod interpreter;
+
+pub use interpreter::{Error, Interpreter};
@@ -0,0 +1,293 @@
+use std::cell::{Cell, RefCell};
+use std::mem::size_of;
+use std::time::{Duration, Instant};
+
+use crate::object::{Object, LIMIT_ERROR};
+
+/// Execution budgets for a script. Exceeding one stops evaluation with a
+/// "limit exceeded" runtime error instead of hanging or overflowing the
+/// native stack. `None` means unlimited.
+///
+/// ```
+/// use monkey::{limits::Limits, Interpreter};
+///
+/// let mut interpreter = Interpreter::new();
+/// interpreter.set_limits(Limits { max_depth: Some(50), ..Limits::default() });
+/// let err = interpreter.eval_str("let f = fn(n) { 1 + f(n + 1) }; f(0)").unwrap_err();
+/// assert!(err.to_string().contains("limit exceeded"));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    /// Number of expressions evaluated.
+    pub max_steps: Option<u64>,
+    /// Number of nested calls of Monkey functions.
+    pub max_depth: Option<usize>,
+    /// Wall-clock time for one `eval_str` or `call`.
+    pub timeout: Option<Duration>,
+    /// Approximate bytes of all the strings, arrays and hashes created over
+    /// the whole evaluation. This is not a ceiling on the live heap: nothing
+    /// is credited back when a value is dropped, so a long-running script
+    /// can exceed it while holding very little.
+    pub max_total_allocation: Option<usize>
+}
+
+/// Calls don't use the native stack, so this only guards against runaway
+/// recursion eating the heap.
+pub const DEFAULT_MAX_DEPTH: usize = 100_000;
+
+/// A builtin that calls back into Monkey starts a new evaluation on the
+/// native stack, so callbacks nested inside callbacks are capped apart
+/// from `max_depth`.
+pub const MAX_CALLBACK_DEPTH: usize = 1_000;
+
+/// The clock is only read every this many steps.
+const CLOCK_INTERVAL: u64 = 1024;
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_steps: None,
+            max_depth: Some(DEFAULT_MAX_DEPTH),
+            timeout: None,
+            max_total_allocation: None
+        }
+    }
+}
+
+impl Limits {
+    pub fn unlimited() -> Self {
+        Limits {
+            max_steps: None,
+            max_depth: None,
+            timeout: None,
+            max_total_allocation: None
+        }
+    }
+}
+
+struct Budget {
+    limits: Limits,
+    steps: u64,
+    depth: usize,
+    allocated: usize,
+    deadline: Option<Instant>
+}
+
+thread_local! {
+    static BUDGET: RefCell<Option<Budget>> = const { RefCell::new(None) };
+    static CALLBACKS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Puts `limits` in force on this thread until the guard is dropped. The
+/// budget being replaced, if any, comes back into force afterwards.
+pub(crate) fn start(limits: Limits) -> BudgetGuard {
+    let budget = Budget {
+        limits,
+        steps: 0,
+        depth: 0,
+        allocated: 0,
+        deadline: limits.timeout.map(|timeout| Instant::now() + timeout)
+    };
+    BudgetGuard {
+        previous: BUDGET.with(|current| current.replace(Some(budget)))
+    }
+}
+
+/// The limits in force on this thread, if evaluation is under way.
+pub(crate) fn current() -> Option<Limits> {
+    BUDGET.with(|current| current.borrow().as_ref().map(|budget| budget.limits))
+}
+
+pub(crate) struct BudgetGuard {
+    previous: Option<Budget>
+}
+
+impl Drop for BudgetGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        BUDGET.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+/// Counts one evaluation step, checking the step limit and the timeout.
+pub(crate) fn step() -> Result<(), String> {
+    BUDGET.with(|current| {
+        let mut current = current.borrow_mut();
+        let budget = match current.as_mut() {
+            Some(budget) => budget,
+            None => return Ok(())
+        };
+        budget.steps += 1;
+        if let Some(max) = budget.limits.max_steps {
+            if budget.steps > max {
+                return Err(format!("limit exceeded: more than {} steps", max));
+            }
+        }
+        if let Some(deadline) = budget.deadline {
+            if budget.steps % CLOCK_INTERVAL == 0 && Instant::now() >= deadline {
+                return Err(format!("limit exceeded: timed out after {:?}", budget.limits.timeout.unwrap()));
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Enters a function call, checking the call depth limit. Every successful
+/// `enter_call` must be paired with an `exit_call`.
+pub(crate) fn enter_call() -> Result<(), String> {
+    BUDGET.with(|current| {
+        let mut current = current.borrow_mut();
+        let budget = match current.as_mut() {
+            Some(budget) => budget,
+            None => return Ok(())
+        };
+        if let Some(max) = budget.limits.max_depth {
+            if budget.depth >= max {
+                return Err(format!("limit exceeded: call depth over {}", max));
+            }
+        }
+        budget.depth += 1;
+        Ok(())
+    })
+}
+
+pub(crate) fn exit_call() {
+    BUDGET.with(|current| {
+        if let Some(budget) = current.borrow_mut().as_mut() {
+            budget.depth -= 1;
+        }
+    })
+}
+
+/// Enters a callback from a builtin, checking `MAX_CALLBACK_DEPTH`. Every
+/// successful `enter_callback` must be paired with an `exit_callback`.
+pub(crate) fn enter_callback() -> Result<(), String> {
+    CALLBACKS.with(|callbacks| {
+        if callbacks.get() >= MAX_CALLBACK_DEPTH {
+            return Err(format!("limit exceeded: callbacks nested more than {} deep", MAX_CALLBACK_DEPTH));
+        }
+        callbacks.set(callbacks.get() + 1);
+        Ok(())
+    })
+}
+
+pub(crate) fn exit_callback() {
+    CALLBACKS.with(|callbacks| callbacks.set(callbacks.get() - 1));
+}
+
+/// Charges the heap memory of a newly created value against the
+/// allocation budget. Only the value's own allocation counts: its elements were
+/// charged when they were created.
+pub(crate) fn track(object: Object) -> Object {
+    let bytes = match &object {
+        Object::String{value} => value.len(),
+        Object::Array{elements} => elements.len() * size_of::<Object>(),
+        Object::HashM{pairs} => pairs.len() * 2 * size_of::<Object>(),
+        _ => return object
+    };
+    BUDGET.with(|current| {
+        let mut current = current.borrow_mut();
+        let budget = match current.as_mut() {
+            Some(budget) => budget,
+            None => return object
+        };
+        budget.allocated = budget.allocated.saturating_add(bytes);
+        match budget.limits.max_total_allocation {
+            Some(max) if budget.allocated > max => allocation_exceeded(max),
+            _ => object
+        }
+    })
+}
+
+/// Checks that `bytes` more would still fit the allocation budget, before a
+/// large value is allocated. Nothing is charged until the value is
+/// `track`ed.
+pub(crate) fn reserve(bytes: usize) -> Result<(), Object> {
+    BUDGET.with(|current| {
+        let current = current.borrow();
+        let budget = match current.as_ref() {
+            Some(budget) => budget,
+            None => return Ok(())
+        };
+        match budget.limits.max_total_allocation {
+            Some(max) if budget.allocated.saturating_add(bytes) > max => Err(allocation_exceeded(max)),
+            _ => Ok(())
+        }
+    })
+}
+
+/// `reserve` for an array of `count` elements.
+pub(crate) fn reserve_elements(count: usize) -> Result<(), Object> {
+    match count.checked_mul(size_of::<Object>()).filter(|bytes| *bytes <= isize::MAX as usize) {
+        Some(bytes) => reserve(bytes),
+        None => Err(Object::new_error(format!("an array of {} elements is too large", count)))
+    }
+}
+
+fn allocation_exceeded(max: usize) -> Object {
+    Object::new_error_of(LIMIT_ERROR, format!("limit exceeded: more than {} bytes allocated", max))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Limits;
+    use crate::interpreter::{Error, Interpreter};
+
+    fn eval_with(limits: Limits, source: &str) -> Result<String, Error> {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_limits(limits);
+        interpreter.eval_str(source).map(|result| result.to_string())
+    }
+
+    #[test]
+    fn exceeded_limits_cannot_be_caught() {
+        let spin = "let spin = fn(n) { spin(n + 1) };";
+        let cases = [
+            (Limits { max_steps: Some(1000), ..Limits::default() }, "limit exceeded: more than 1000 steps"),
+            (Limits { timeout: Some(Duration::from_millis(10)), ..Limits::default() }, "limit exceeded: timed out after 10ms")
+        ];
+        for (limits, message) in cases {
+            let exceeded = Err(Error::Runtime(message.to_string()));
+            assert_eq!(eval_with(limits, &format!("{} spin(0)", spin)), exceeded);
+            assert_eq!(eval_with(limits, &format!("{} try {{ spin(0) }} catch (e) {{ 0 }} finally {{ 1 }}", spin)), exceeded);
+        }
+        let deep = Limits { max_depth: Some(50), ..Limits::default() };
+        let recurse = "let f = fn(n) { if (n == 0) { 0 } else { 1 + f(n - 1) } };";
+        assert_eq!(eval_with(deep, &format!("{} f(40)", recurse)), Ok("40".to_string()));
+        assert_eq!(eval_with(deep, &format!("{} try {{ f(60) }} catch (e) {{ 0 }}", recurse)),
+            Err(Error::Runtime("limit exceeded: call depth over 50".to_string())));
+    }
+
+    #[test]
+    fn checks_the_allocation_budget_before_allocating() {
+        let limits = Limits { max_total_allocation: Some(1_000_000), ..Limits::default() };
+        let exceeded = Err(Error::Runtime("limit exceeded: more than 1000000 bytes allocated".to_string()));
+        for source in [
+            "repeat(\"x\", 100000000000)",
+            "range(0, 10000000000)",
+            "pad_left(\"a\", 100000000000, \"0\")",
+            "replace(repeat(\"a\", 1000), \"a\", repeat(\"b\", 1000))",
+            "let s = repeat(\"x\", 400000); s + s + s",
+            "let f = fn(a, n) { if (n == 0) { a } else { f(push(a, n), n - 1) } }; f([], 100000)",
+            "try { repeat(\"x\", 100000000000) } catch (e) { \"caught\" }"
+        ] {
+            assert_eq!(eval_with(limits, source), exceeded, "{}", source);
+        }
+        assert_eq!(eval_with(limits, "len(range(0, 1000))"), Ok("1000".to_string()));
+        let small = Limits { max_total_allocation: Some(100), ..Limits::default() };
+        let index = "let s = \"ab\"; let f = fn(n) { if (n == 0) { 0 } else { s[0]; f(n - 1) } };";
+        assert_eq!(eval_with(small, &format!("{} f(50)", index)), Ok("0".to_string()));
+        assert_eq!(eval_with(small, &format!("{} f(200)", index)),
+            Err(Error::Runtime("limit exceeded: more than 100 bytes allocated".to_string())));
+    }
+
+    #[test]
+    fn rejects_sizes_that_overflow() {
+        assert_eq!(eval_with(Limits::default(), "repeat(\"ab\", 9223372036854775807)"),
+            Err(Error::Runtime("result of `repeat` is too large".to_string())));
+        assert_eq!(eval_with(Limits::default(), "range(-9223372036854775807, 9223372036854775807)"),
+            Err(Error::Runtime("an array of 9223372036854775807 elements is too large".to_string())));
+    }
+}
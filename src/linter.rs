@@ -0,0 +1,326 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::{Expression, Pattern, Program, Statement};
+use crate::builtins::Builtins;
+use crate::lexer::{Lexer, Token, TokenType};
+use crate::parser::{Error, Parser};
+use crate::resolver::LetNames;
+use crate::visitor::{walk_expression, walk_statement, Visitor};
+
+pub const UNUSED_VARIABLE: &str = "unused-variable";
+pub const SHADOWED_BUILTIN: &str = "shadowed-builtin";
+pub const UNREACHABLE_CODE: &str = "unreachable-code";
+pub const CONSTANT_CONDITION: &str = "constant-condition";
+pub const NON_EXHAUSTIVE_MATCH: &str = "non-exhaustive-match";
+
+pub const RULES: [&str; 5] = [UNUSED_VARIABLE, SHADOWED_BUILTIN, UNREACHABLE_CODE, CONSTANT_CONDITION, NON_EXHAUSTIVE_MATCH];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub rule: &'static str,
+    pub message: String,
+    pub line: usize,
+    pub column: usize
+}
+
+impl Diagnostic {
+    fn new(rule: &'static str, token: &Token, message: String) -> Self {
+        Diagnostic {
+            rule,
+            message,
+            line: token.line,
+            column: token.column
+        }
+    }
+
+    pub fn to_json(&self, file: &str) -> String {
+        format!("{{\"file\": {}, \"line\": {}, \"column\": {}, \"rule\": {}, \"message\": {}}}",
+            json_string(file), self.line, self.column, json_string(self.rule), json_string(&self.message))
+    }
+}
+
+/// Lints `source`, skipping the rules in `allowed`.
+///
+/// A single line can also opt out with `// lint: allow(unused-variable)`,
+/// either trailing that line or on its own line just above it.
+pub fn lint_source(source: &str, allowed: &[String]) -> Result<Vec<Diagnostic>, Error> {
+    let mut lexer = Lexer::new(source.to_string());
+    let mut code_lines = HashSet::new();
+    loop {
+        let token = lexer.next_token();
+        if token.token_type == TokenType::EOF {
+            break;
+        }
+        code_lines.insert(token.line);
+    }
+    let mut suppressed = HashSet::new();
+    for comment in lexer.comments() {
+        if let Some(rule) = suppression(&comment.text) {
+            let line = if code_lines.contains(&comment.line) { comment.line } else { comment.line + 1 };
+            suppressed.insert((line, rule.to_string()));
+        }
+    }
+
+    let mut parser = Parser::new(Lexer::new(source.to_string()));
+    let program = parser.parse_program()?;
+    let mut linter = Linter::new();
+    linter.visit_program(&program);
+
+    let mut diagnostics: Vec<Diagnostic> = linter.diagnostics.into_iter()
+        .filter(|d| !allowed.iter().any(|rule| rule == d.rule))
+        .filter(|d| !suppressed.contains(&(d.line, d.rule.to_string())))
+        .collect();
+    diagnostics.sort_by_key(|d| (d.line, d.column));
+    Ok(diagnostics)
+}
+
+fn suppression(comment: &str) -> Option<&str> {
+    let text = comment.trim_start_matches('/').trim();
+    let rule = text.strip_prefix("lint:")?.trim().strip_prefix("allow(")?.strip_suffix(')')?;
+    Some(rule.trim())
+}
+
+struct Binding {
+    token: Token,
+    used: bool
+}
+
+struct Linter {
+    builtins: HashSet<String>,
+    scopes: Vec<HashMap<String, Binding>>,
+    diagnostics: Vec<Diagnostic>
+}
+
+impl Linter {
+    fn new() -> Self {
+        Linter {
+            builtins: Builtins::new().builtins.store.keys().cloned().collect(),
+            scopes: Vec::new(),
+            diagnostics: Vec::new()
+        }
+    }
+
+    /// Opens a scope holding `parameters` and the names `collect` finds,
+    /// such as every `let` in a function's body. Lets are hoisted like the resolver does, so calls between local closures
+    /// count as uses whichever order they are defined in.
+    fn push_scope<F: Fn(&mut LetNames)>(&mut self, parameters: &[Expression], collect: F) {
+        let mut scope = HashMap::new();
+        for parameter in parameters {
+            if let Expression::Identifier{token, value} = parameter.parameter_name() {
+                self.check_builtin(token, value);
+                scope.insert(value.clone(), Binding{token: token.clone(), used: true});
+            }
+        }
+        let mut names = LetNames::default();
+        collect(&mut names);
+        for token in names.0 {
+            scope.entry(token.literal.clone()).or_insert(Binding{token, used: false});
+        }
+        self.scopes.push(scope);
+    }
+
+    fn pop_scope(&mut self) {
+        let scope = self.scopes.pop().unwrap();
+        for (name, binding) in scope {
+            if !binding.used {
+                self.diagnostics.push(Diagnostic::new(UNUSED_VARIABLE, &binding.token,
+                    format!("`{}` is never used", name)));
+            }
+        }
+    }
+
+    fn check_builtin(&mut self, token: &Token, name: &str) {
+        if self.builtins.contains(name) {
+            self.diagnostics.push(Diagnostic::new(SHADOWED_BUILTIN, token,
+                format!("`{}` shadows the builtin of the same name", name)));
+        }
+    }
+
+    fn check_reachable(&mut self, statements: &[Statement]) {
+        let mut exit = None;
+        for statement in statements {
+            if let Some(keyword) = exit {
+                self.diagnostics.push(Diagnostic::new(UNREACHABLE_CODE, statement.token(),
+                    format!("unreachable code after `{}`", keyword)));
+                return;
+            }
+            exit = match statement {
+                Statement::ReturnStatement{..} => Some("return"),
+                Statement::ThrowStatement{..} => Some("throw"),
+                _ => None
+            };
+        }
+    }
+}
+
+impl Visitor for Linter {
+    fn visit_program(&mut self, program: &Program) {
+        self.push_scope(&[], |names| names.visit_program(program));
+        self.check_reachable(&program.statements);
+        for statement in &program.statements {
+            self.visit_statement(statement);
+        }
+        self.pop_scope();
+    }
+
+    fn visit_pattern(&mut self, pattern: &Pattern) {
+        // The bound names are not uses of the variables.
+        for binding in pattern.bindings() {
+            if let Expression::Identifier{token, value} = binding {
+                self.check_builtin(token, value);
+            }
+        }
+    }
+
+    fn visit_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::BlockStatment{token:_, statements} => {
+                self.check_reachable(statements);
+                walk_statement(self, statement);
+            },
+            _ => walk_statement(self, statement)
+        }
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::Identifier{token:_, value} | Expression::Local{token:_, value, ..} => {
+                if let Some(binding) = self.scopes.iter_mut().rev().find_map(|scope| scope.get_mut(value)) {
+                    binding.used = true;
+                }
+            },
+            Expression::MatchExpression{token, subject, arms} => {
+                if !arms.iter().any(|arm| arm.guard.is_none() && arm.pattern.is_irrefutable()) {
+                    self.diagnostics.push(Diagnostic::new(NON_EXHAUSTIVE_MATCH, token,
+                        "`match` has no catch-all arm, so a value matching no arm is an error".to_string()));
+                }
+                self.visit_expression(subject);
+                for arm in arms {
+                    self.push_scope(&[], |names| names.visit_arm(arm));
+                    self.visit_pattern(&arm.pattern);
+                    if let Some(guard) = &arm.guard {
+                        self.visit_expression(guard);
+                    }
+                    self.visit_statement(&arm.body);
+                    self.pop_scope();
+                }
+            },
+            Expression::IfExpression{token, condition, ..} => {
+                if is_literal(condition) {
+                    self.diagnostics.push(Diagnostic::new(CONSTANT_CONDITION, token,
+                        "`if` condition is a literal, so it always takes the same branch".to_string()));
+                }
+                walk_expression(self, expression);
+            },
+            Expression::TryExpression{token:_, body, catch, finally} => {
+                self.visit_statement(body);
+                if let Some(catch) = catch {
+                    self.push_scope(&[], |names| names.visit_catch(catch));
+                    if let Some(parameter) = &catch.parameter {
+                        self.visit_pattern(parameter);
+                    }
+                    self.visit_statement(&catch.body);
+                    self.pop_scope();
+                }
+                if let Some(finally) = finally {
+                    self.visit_statement(finally);
+                }
+            },
+            Expression::FunctionLiteral{token:_, parameters, body} => {
+                self.push_scope(parameters, |names| names.visit_statement(body));
+                for parameter in parameters {
+                    if let Expression::Named{value, ..} = parameter {
+                        self.visit_expression(value);
+                    }
+                }
+                self.visit_statement(body);
+                self.pop_scope();
+            },
+            _ => walk_expression(self, expression)
+        }
+    }
+}
+
+fn is_literal(expression: &Expression) -> bool {
+    matches!(expression,
+        Expression::IntegerLiteral{..}
+        | Expression::StringLiteral{..}
+        | Expression::Boolean{..}
+        | Expression::ArrayLiteral{..}
+        | Expression::HashLiteral{..}
+        | Expression::FunctionLiteral{..})
+}
+
+fn json_string(value: &str) -> String {
+    let mut out = String::from("\"");
+    for ch in value.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            ch if (ch as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => out.push(ch)
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lint_source, Diagnostic, CONSTANT_CONDITION, NON_EXHAUSTIVE_MATCH, SHADOWED_BUILTIN, UNREACHABLE_CODE,
+        UNUSED_VARIABLE};
+
+    fn rules(source: &str) -> Vec<&'static str> {
+        lint_source(source, &[]).unwrap().into_iter().map(|d| d.rule).collect()
+    }
+
+    #[test]
+    fn reports_each_rule() {
+        let cases = [
+            (UNUSED_VARIABLE, "let f = fn() { let x = 1; 2 }; f()", "let f = fn() { let x = 1; x }; f()"),
+            (SHADOWED_BUILTIN, "let len = 1; len", "let size = 1; size"),
+            (UNREACHABLE_CODE, "let f = fn() { return 1; 2 }; f()", "let f = fn() { 2; return 1; }; f()"),
+            (CONSTANT_CONDITION, "let x = 1; if (true) { x }", "let x = 1; if (x) { x }"),
+            (NON_EXHAUSTIVE_MATCH, "let x = 1; match (x) { 1 => 2 }", "let x = 1; match (x) { 1 => 2, _ => 3 }")
+        ];
+        for (rule, bad, good) in cases {
+            assert_eq!(rules(bad), [rule], "{}", bad);
+            assert_eq!(rules(good), Vec::<&str>::new(), "{}", good);
+        }
+    }
+
+    #[test]
+    fn reports_positions_in_order() {
+        let diagnostics = lint_source("let f = fn() {\n  let a = 1;\n  let b = 2;\n  3\n};\nf()", &[]).unwrap();
+        let positions: Vec<(usize, usize)> = diagnostics.iter().map(|d| (d.line, d.column)).collect();
+        assert_eq!(positions, [(2, 7), (3, 7)]);
+        assert_eq!(diagnostics[0].message, "`a` is never used");
+    }
+
+    #[test]
+    fn skips_allowed_and_suppressed_rules() {
+        let source = "let len = 1; len";
+        assert!(lint_source(source, &[SHADOWED_BUILTIN.to_string()]).unwrap().is_empty());
+        assert!(rules("let len = 1; len // lint: allow(shadowed-builtin)").is_empty());
+        assert!(rules("// lint: allow(shadowed-builtin)\nlet len = 1; len").is_empty());
+        // A suppression only covers its own rule and its own line.
+        assert_eq!(rules("let len = 1; len // lint: allow(unused-variable)"), [SHADOWED_BUILTIN]);
+        assert_eq!(rules("// lint: allow(shadowed-builtin)\n\nlet len = 1; len"), [SHADOWED_BUILTIN]);
+    }
+
+    #[test]
+    fn writes_diagnostics_as_json() {
+        let diagnostic = Diagnostic {
+            rule: UNUSED_VARIABLE,
+            message: "`a` is \"never\" used".to_string(),
+            line: 2,
+            column: 7
+        };
+        assert_eq!(diagnostic.to_json("dir\\a.mn"),
+            "{\"file\": \"dir\\\\a.mn\", \"line\": 2, \"column\": 7, \"rule\": \"unused-variable\", \
+            \"message\": \"`a` is \\\"never\\\" used\"}");
+    }
+}
@@ -1,43 +1,163 @@
-
-use std::{cell::RefCell, io::{self, Write}};
-pub mod lexer;
-pub mod ast;
-pub mod parser;
-pub mod object;
-pub mod evaluator;
-pub mod environment;
-pub mod builtins;
-use builtins::Builtins;
-use evaluator::Evaluator;
-use lexer::{Lexer};
-use parser::Parser;
-use std::rc::Rc;
+use std::io::{self, Read, Write};
 use std::env;
 use std::fs;
+use std::process;
+
+use monkey::{formatter, linter, Interpreter};
+use monkey::builtins::{Builtins, Capability};
+use monkey::object::Object;
+
+
+
+/// `monkey fmt [--check] [files...]`
+///
+/// Rewrites each file in place, or with `--check` only reports the files
+/// that would change and exits with 1. Without files, formats stdin to stdout.
+fn fmt(args: &[String]) -> i32 {
+    let check = args.iter().any(|arg| arg == "--check");
+    let files: Vec<&String> = args.iter().filter(|arg| *arg != "--check").collect();
 
+    if files.is_empty() {
+        let mut source = String::new();
+        io::stdin().read_to_string(&mut source).unwrap();
+        return match formatter::format_source(&source) {
+            Ok(formatted) if check => if formatted == source { 0 } else { 1 },
+            Ok(formatted) => {
+                print!("{}", formatted);
+                0
+            },
+            Err(err) => {
+                eprintln!("<stdin>: {}", err);
+                2
+            }
+        };
+    }
+
+    let mut status = 0;
+    for file in files {
+        let source = match fs::read_to_string(file) {
+            Ok(source) => source,
+            Err(err) => {
+                eprintln!("{}: {}", file, err);
+                status = 2;
+                continue;
+            }
+        };
+        match formatter::format_source(&source) {
+            Ok(formatted) if formatted == source => {},
+            Ok(_) if check => {
+                println!("{}", file);
+                status = status.max(1);
+            },
+            Ok(formatted) => fs::write(file, formatted).expect("Something went wrong writing the file"),
+            Err(err) => {
+                eprintln!("{}: {}", file, err);
+                status = 2;
+            }
+        }
+    }
+    status
+}
 
+/// `monkey lint [--format text|json] [--allow <rule>]... [files...]`
+///
+/// Prints one diagnostic per line (or a JSON array with `--format json`)
+/// and exits with 1 when anything was reported. Without files, lints stdin.
+fn lint(args: &[String]) -> i32 {
+    let mut json = false;
+    let mut allowed = Vec::new();
+    let mut files = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => match args.next().map(String::as_str) {
+                Some("text") => json = false,
+                Some("json") => json = true,
+                Some(format) => {
+                    eprintln!("unknown format `{}`, expected one of: text, json", format);
+                    return 2;
+                },
+                None => {
+                    eprintln!("`--format` needs a value, one of: text, json");
+                    return 2;
+                }
+            },
+            "--allow" => allowed.extend(args.next().cloned()),
+            _ => files.push(arg.clone())
+        }
+    }
+    for rule in &allowed {
+        if !linter::RULES.contains(&rule.as_str()) {
+            eprintln!("unknown lint rule `{}`, expected one of: {}", rule, linter::RULES.join(", "));
+            return 2;
+        }
+    }
+
+    let mut sources = Vec::new();
+    if files.is_empty() {
+        let mut source = String::new();
+        io::stdin().read_to_string(&mut source).unwrap();
+        sources.push(("<stdin>".to_string(), source));
+    }
+    for file in files {
+        match fs::read_to_string(&file) {
+            Ok(source) => sources.push((file, source)),
+            Err(err) => {
+                eprintln!("{}: {}", file, err);
+                return 2;
+            }
+        }
+    }
+
+    let mut status = 0;
+    let mut reports = Vec::new();
+    for (file, source) in sources {
+        match linter::lint_source(&source, &allowed) {
+            Ok(diagnostics) => {
+                for d in diagnostics {
+                    status = status.max(1);
+                    if json {
+                        reports.push(d.to_json(&file));
+                    } else {
+                        println!("{}:{}:{}: {}: {}", file, d.line, d.column, d.rule, d.message);
+                    }
+                }
+            },
+            Err(err) => {
+                eprintln!("{}: {}", file, err);
+                status = 2;
+            }
+        }
+    }
+    if json {
+        println!("[{}]", reports.join(",\n "));
+    }
+    status
+}
+
+fn report(result: Result<Object, monkey::Error>) {
+    match result {
+        Ok(object) => println!("{:#?}", object),
+        Err(monkey::Error::Exit(code)) => process::exit(code),
+        Err(err) => println!("{}", err)
+    }
+}
 
 fn main() {
     let args: Vec<String> = env::args().collect();
+    if args.len() >= 2 && args[1] == "fmt" {
+        process::exit(fmt(&args[2..]));
+    }
+    if args.len() >= 2 && args[1] == "lint" {
+        process::exit(lint(&args[2..]));
+    }
     //let definitions = Definitions::new();
     //let inst = definitions.make(code::OpConstant, vec![25]);
     //println!("{:?}", inst);
 
-    let b = Builtins::new();
-    let environment = b.builtins.create_child();
-    let env= Rc::new(RefCell::new(environment));
+    let mut interpreter = Interpreter::with_builtins(Builtins::with_capabilities(Capability::ALL));
     if args.len() == 2 {
-        let contents = fs::read_to_string(args[1].clone())
-        .expect("Something went wrong reading the file");
-        let lexer = Lexer::new(contents);
-        let mut parser = Parser::new(lexer);
-        let program = parser.parse_program();
-        if program.is_ok() {
-            let ev = program.unwrap();
-            println!("{:#?}", ev.evaluate(env.clone()));
-        } else {
-            println!("{:#?}", program.err());
-        }
+        report(interpreter.eval_file(&args[1]));
     } else {
         loop {
             print!(">> ");
@@ -45,18 +165,10 @@ fn main() {
     
             let mut buffer = String::new();
             let stdin = io::stdin(); // We get `Stdin` here.
-            stdin.read_line(&mut buffer).unwrap();
-            let lexer = Lexer::new(buffer);
-            let mut parser = Parser::new(lexer);
-            let program = parser.parse_program();
-            if program.is_ok() {
-                let ev = program.unwrap();
-                println!("{:#?}", ev.evaluate(env.clone()));
-            } else {
-                println!("{:#?}", program.err());
+            if stdin.read_line(&mut buffer).unwrap() == 0 {
+                break;
             }
-            
+            report(interpreter.eval_str(&buffer));
         }
     }
 }
-
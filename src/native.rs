@@ -0,0 +1,258 @@
+use std::any::{type_name, Any};
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+use crate::convert::{FromObject, IntoObject, NativeFunction};
+use crate::object::{Builtin, Object};
+
+/// A host value exposed to scripts, e.g. a database handle. Scripts can
+/// only reach it through the methods and properties of its class.
+#[derive(Clone)]
+pub struct Native {
+    pub value: Rc<dyn Any>,
+    pub class: Rc<NativeClass>
+}
+
+type Getter = Box<dyn Fn(&dyn Any) -> Object>;
+type Display = Box<dyn Fn(&dyn Any) -> String>;
+type Equals = Box<dyn Fn(&dyn Any, &dyn Any) -> bool>;
+type Hashing = Box<dyn Fn(&dyn Any) -> u64>;
+
+/// Describes a host type: its name, the methods and properties scripts can
+/// use with `value.name`, and optional hooks for printing, `==` and hashing.
+/// Without hooks a native value prints as `<Name>` and compares by identity.
+pub struct NativeClass {
+    name: String,
+    methods: HashMap<String, Rc<Builtin>>,
+    properties: HashMap<String, Getter>,
+    display: Option<Display>,
+    equals: Option<Equals>,
+    hash: Option<Hashing>
+}
+
+impl NativeClass {
+    /// Starts describing the host type `T`, called `name` in scripts.
+    ///
+    /// ```
+    /// use std::rc::Rc;
+    /// use monkey::{native::NativeClass, Interpreter};
+    ///
+    /// struct Counter { start: i64 }
+    ///
+    /// let class = NativeClass::builder::<Counter>("Counter")
+    ///     .property("start", |c: &Counter| c.start)
+    ///     .method("plus", |c: Rc<Counter>, n: i64| c.start + n)
+    ///     .build();
+    /// let mut interpreter = Interpreter::new();
+    /// interpreter.set_global("counter", class.wrap(Counter { start: 40 }));
+    /// let result = interpreter.eval_str("counter.plus(2) == counter.start + 2").unwrap();
+    /// assert_eq!(result, monkey::object::Object::new_bool(true));
+    /// ```
+    pub fn builder<T: 'static>(name: &str) -> NativeClassBuilder<T> {
+        NativeClassBuilder {
+            class: NativeClass {
+                name: name.to_string(),
+                methods: HashMap::new(),
+                properties: HashMap::new(),
+                display: None,
+                equals: None,
+                hash: None
+            },
+            marker: PhantomData
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// A built class for the host type `T`, which wraps values of that type.
+pub struct TypedClass<T> {
+    class: Rc<NativeClass>,
+    marker: PhantomData<fn(T)>
+}
+
+impl<T> Clone for TypedClass<T> {
+    fn clone(&self) -> Self {
+        TypedClass {
+            class: self.class.clone(),
+            marker: PhantomData
+        }
+    }
+}
+
+impl<T: 'static> TypedClass<T> {
+    /// Wraps a host value into a Monkey object of this class.
+    pub fn wrap(&self, value: T) -> Object {
+        Object::Native(Native {
+            value: Rc::new(value),
+            class: self.class.clone()
+        })
+    }
+
+    pub fn class(&self) -> &Rc<NativeClass> {
+        &self.class
+    }
+
+    pub fn name(&self) -> &str {
+        &self.class.name
+    }
+}
+
+pub struct NativeClassBuilder<T> {
+    class: NativeClass,
+    marker: PhantomData<T>
+}
+
+impl<T: 'static> NativeClassBuilder<T> {
+    /// Adds a method. The function gets the receiver as its first argument,
+    /// as an `Rc<T>`, followed by the arguments of the call.
+    pub fn method<Args, F>(mut self, name: &str, function: F) -> Self
+        where F: NativeFunction<Args> + 'static {
+        let method_name = format!("{}.{}", self.class.name, name);
+        let builtin = Rc::new(Builtin{
+            func: Box::new(move |args| function.call(&method_name, args))
+        });
+        self.class.methods.insert(name.to_string(), builtin);
+        self
+    }
+
+    pub fn property<R, F>(mut self, name: &str, getter: F) -> Self
+        where R: IntoObject, F: Fn(&T) -> R + 'static {
+        self.class.properties.insert(name.to_string(), Box::new(move |value| {
+            getter(value.downcast_ref::<T>().unwrap()).into_object()
+        }));
+        self
+    }
+
+    pub fn display<F: Fn(&T) -> String + 'static>(mut self, display: F) -> Self {
+        self.class.display = Some(Box::new(move |value| display(value.downcast_ref::<T>().unwrap())));
+        self
+    }
+
+    /// Compares values of this class with `T`'s `PartialEq`. Values that
+    /// are equal must hash alike, so this also turns on `hashing`.
+    pub fn equality(mut self) -> Self where T: PartialEq + Hash {
+        self.class.equals = Some(Box::new(|left, right| {
+            left.downcast_ref::<T>() == right.downcast_ref::<T>()
+        }));
+        self.hashing()
+    }
+
+    /// Hashes values of this class with `T`'s `Hash`, so they can be used
+    /// as hash keys by value rather than by identity.
+    pub fn hashing(mut self) -> Self where T: Hash {
+        self.class.hash = Some(Box::new(|value| {
+            let mut hasher = DefaultHasher::new();
+            value.downcast_ref::<T>().unwrap().hash(&mut hasher);
+            hasher.finish()
+        }));
+        self
+    }
+
+    pub fn build(self) -> TypedClass<T> {
+        TypedClass {
+            class: Rc::new(self.class),
+            marker: PhantomData
+        }
+    }
+}
+
+impl Native {
+    /// Looks up `name` on the value: a property gives its value, a method
+    /// gives a builtin bound to this receiver.
+    pub fn member(&self, name: &str) -> Option<Object> {
+        if let Some(getter) = self.class.properties.get(name) {
+            return Some(getter(self.value.as_ref()));
+        }
+        let method = self.class.methods.get(name)?.clone();
+        let receiver = Object::Native(self.clone());
+        Some(Object::Builtin(Rc::new(Builtin{
+            func: Box::new(move |mut args| {
+                args.insert(0, receiver.clone());
+                (method.func)(args)
+            })
+        })))
+    }
+}
+
+impl fmt::Debug for Native {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.class.display {
+            Some(display) => write!(f, "{}", display(self.value.as_ref())),
+            None => write!(f, "<{}>", self.class.name)
+        }
+    }
+}
+
+impl PartialEq for Native {
+    fn eq(&self, other: &Native) -> bool {
+        if !Rc::ptr_eq(&self.class, &other.class) {
+            return false;
+        }
+        match &self.class.equals {
+            Some(equals) => equals(self.value.as_ref(), other.value.as_ref()),
+            None => Rc::ptr_eq(&self.value, &other.value)
+        }
+    }
+}
+
+impl Hash for Native {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match &self.class.hash {
+            Some(hash) => hash(self.value.as_ref()).hash(state),
+            None => (Rc::as_ptr(&self.value) as *const u8).hash(state)
+        }
+    }
+}
+
+/// The receiver of a native method, or any argument that must be a
+/// native value of type `T`.
+impl<T: 'static> FromObject for Rc<T> {
+    fn from_object(object: Object) -> Result<Self, String> {
+        match &object {
+            Object::Native(native) => {
+                let class = native.class.name.clone();
+                native.value.clone().downcast::<T>()
+                    .map_err(|_| format!("expected {}, got {}", type_name::<T>(), class))
+            },
+            other => Err(format!("expected {}, got {}", type_name::<T>(), other.type_name()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NativeClass, TypedClass};
+    use crate::interpreter::Interpreter;
+    use crate::object::Object;
+
+    #[derive(PartialEq, Hash)]
+    struct Point {
+        x: i64
+    }
+
+    #[test]
+    fn wraps_values_of_the_class_type() {
+        let class = NativeClass::builder::<Point>("Point").property("x", |p: &Point| p.x).build();
+        assert_eq!(class.name(), "Point");
+        let mut interpreter = Interpreter::new();
+        interpreter.set_global("p", class.wrap(Point { x: 3 }));
+        assert_eq!(interpreter.eval_str("p.x"), Ok(Object::new_int(3)));
+    }
+
+    #[test]
+    fn equal_values_are_the_same_hash_key() {
+        let class: TypedClass<Point> = NativeClass::builder::<Point>("Point").equality().build();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_global("a", class.wrap(Point { x: 1 }));
+        interpreter.set_global("b", class.wrap(Point { x: 1 }));
+        assert_eq!(interpreter.eval_str("[a == b, len(unique([a, b])), {a: 1}[b]]"),
+            Ok(Object::Array{elements: vec![Object::new_bool(true), Object::new_int(1), Object::new_int(1)]}));
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{ast::{Expression, Statement}, environment::Environment};
+use crate::{ast::{Expression, Statement}, environment::Environment, native::Native, visitor::with_stack};
 use std::{cell::RefCell, collections::HashMap, hash::Hash, rc::Rc};
 use std::fmt;
 use std::hash::{ Hasher};
@@ -15,7 +15,19 @@ impl fmt::Debug for Builtin {
     }
 }
 
-#[derive(Debug, Clone)]
+/// The kind of errors raised by the evaluator and builtins.
+pub const RUNTIME_ERROR: &str = "runtime";
+/// The kind of errors from exceeded limits, which `try` can't catch.
+pub const LIMIT_ERROR: &str = "limit";
+/// The kind of errors thrown without a `kind`.
+pub const THROWN_ERROR: &str = "error";
+/// The kind of errors from builtins missing a capability.
+pub const PERMISSION_ERROR: &str = "permission";
+/// The kind of the error `exit(code)` raises, with the code as its message.
+/// Like `LIMIT_ERROR`, `try` can't catch it.
+pub const EXIT_ERROR: &str = "exit";
+
+#[derive(Debug)]
 pub enum Object {
     Integer {
         value: i64
@@ -27,8 +39,8 @@ pub enum Object {
         value: Box<Object>
     },
     Function {
-        parameters: Vec<Expression>,
-        body: Statement,
+        parameters: Rc<Vec<Expression>>,
+        body: Rc<Statement>,
         environment: Rc<RefCell<Environment>>
     },
     String {
@@ -41,10 +53,116 @@ pub enum Object {
     HashM {
         pairs: HashMap<Object, Object>
     },
+    Native(Native),
+    /// `Ok(value)` and `Err(error)`: the result of something that can fail,
+    /// for library code that would rather not throw.
+    Ok {
+        value: Box<Object>
+    },
+    Err {
+        value: Box<Object>
+    },
+    /// `Some(value)` and `None`: a value that may be missing.
+    Some {
+        value: Box<Object>
+    },
+    None,
+    /// A runtime error. Like `Return`, it unwinds evaluation until it
+    /// reaches a `try` or the top of the program. `kind` is "runtime" for
+    /// errors from the evaluator, or whatever a `throw` gave, and `stack`
+    /// lists the calls it unwound through, innermost first.
+    Error {
+        message: String,
+        kind: String,
+        stack: Vec<String>
+    },
     Null
 }
 
+impl Clone for Object {
+    fn clone(&self) -> Self {
+        with_stack(|| match self {
+            Object::Integer{value} => Object::Integer{value: *value},
+            Object::Boolean{value} => Object::Boolean{value: *value},
+            Object::Return{value} => Object::Return{value: value.clone()},
+            Object::Function{parameters, body, environment} => Object::Function {
+                parameters: parameters.clone(),
+                body: body.clone(),
+                environment: environment.clone()
+            },
+            Object::String{value} => Object::String{value: value.clone()},
+            Object::Array{elements} => Object::Array{elements: elements.clone()},
+            Object::Builtin(builtin) => Object::Builtin(builtin.clone()),
+            Object::HashM{pairs} => Object::HashM{pairs: pairs.clone()},
+            Object::Native(native) => Object::Native(native.clone()),
+            Object::Ok{value} => Object::Ok{value: value.clone()},
+            Object::Err{value} => Object::Err{value: value.clone()},
+            Object::Some{value} => Object::Some{value: value.clone()},
+            Object::None => Object::None,
+            Object::Error{message, kind, stack} => Object::Error {
+                message: message.clone(),
+                kind: kind.clone(),
+                stack: stack.clone()
+            },
+            Object::Null => Object::Null
+        })
+    }
+}
+
+/// A placeholder, e.g. for a value moved out with `std::mem::take`.
+impl Default for Object {
+    fn default() -> Self {
+        Object::Null
+    }
+}
+
+// The derived drop glue would recurse once per level of nesting, so a
+// value moves the values nested in it onto an explicit stack and drops
+// them one at a time.
+impl Drop for Object {
+    fn drop(&mut self) {
+        let mut pending = Vec::new();
+        self.take_children(&mut pending);
+        while let Some(mut object) = pending.pop() {
+            object.take_children(&mut pending);
+        }
+    }
+}
+
 impl Object {
+    /// Moves the values directly inside an array, hash or wrapper onto
+    /// `pending`.
+    fn take_children(&mut self, pending: &mut Vec<Object>) {
+        match self {
+            Object::Array{elements} => pending.append(elements),
+            Object::HashM{pairs} => {
+                for (key, value) in pairs.drain() {
+                    pending.push(key);
+                    pending.push(value);
+                }
+            },
+            _ => pending.extend(self.take_inner())
+        }
+    }
+
+    /// Moves out the value inside a `Return`, `Ok`, `Err` or `Some`.
+    pub(crate) fn take_inner(&mut self) -> Option<Object> {
+        match self {
+            Object::Return{value} | Object::Ok{value} | Object::Err{value} | Object::Some{value} => {
+                Some(std::mem::take(&mut **value))
+            },
+            _ => None
+        }
+    }
+
+    /// The value of a `return`, or the object itself.
+    pub(crate) fn unwrap_return(mut self) -> Object {
+        match self {
+            Object::Return{..} => self.take_inner().unwrap_or_default(),
+            _ => self
+        }
+    }
+
     pub fn new_int(value: i64) -> Self {
         Object::Integer{
             value
@@ -62,62 +180,188 @@ impl Object {
             value
         }
     }
-}
 
-impl Hash for Object {
-    fn hash<H: Hasher>(&self, state: &mut H) {
+    pub fn new_error(message: String) -> Self {
+        Object::new_error_of(RUNTIME_ERROR, message)
+    }
+
+    pub fn new_error_of(kind: &str, message: String) -> Self {
+        Object::Error{
+            message,
+            kind: kind.to_string(),
+            stack: Vec::new()
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        matches!(self, Object::Error{..})
+    }
+
+    pub fn type_name(&self) -> &'static str {
         match self {
-            Object::Array{elements} => {
-                elements.hash(state);
-            }
-            Object::Integer{value} => {
-                value.hash(state);
-            }
-            Object::Boolean{value} => {
-                value.hash(state);
-            }
-            Object::String{value} => {
-                value.hash(state);
-            }
-            _ => {
-                panic!("no impl for hash");
-            }
+            Object::Integer{..} => "INTEGER",
+            Object::Boolean{..} => "BOOLEAN",
+            Object::Return{..} => "RETURN_VALUE",
+            Object::Function{..} => "FUNCTION",
+            Object::String{..} => "STRING",
+            Object::Array{..} => "ARRAY",
+            Object::Builtin(_) => "BUILTIN",
+            Object::HashM{..} => "HASH",
+            Object::Native(_) => "NATIVE",
+            Object::Ok{..} => "OK",
+            Object::Err{..} => "ERR",
+            Object::Some{..} => "SOME",
+            Object::None => "NONE",
+            Object::Error{..} => "ERROR",
+            Object::Null => "NULL"
         }
     }
-}
 
-impl PartialEq for Object {
-    fn eq(&self, other: &Object) -> bool {
+    /// The truthiness rule used by `if`, `!`, `&&` and `||`: `null`,
+    /// `false`, `0`, `""`, `[]`, `{}`, `Err(..)` and `None` are falsy,
+    /// every other value, including functions and builtins, is truthy.
+    pub fn is_truthy(&self) -> bool {
         match self {
+            Object::Null | Object::Error{..} | Object::Err{..} | Object::None => false,
+            Object::Ok{..} | Object::Some{..} => true,
+            Object::Boolean{value} => *value,
+            Object::Integer{value} => *value != 0,
+            Object::String{value} => !value.is_empty(),
+            Object::Array{elements} => !elements.is_empty(),
+            Object::HashM{pairs} => !pairs.is_empty(),
+            Object::Return{value} => value.is_truthy(),
+            Object::Function{..} | Object::Builtin(_) | Object::Native(_) => true
+        }
+    }
+
+    /// Whether the value can be used as a hash key.
+    pub fn is_hashable(&self) -> bool {
+        with_stack(|| match self {
+            Object::Integer{..} | Object::Boolean{..} | Object::String{..} | Object::Native(_) => true,
+            Object::Array{elements} => elements.iter().all(Object::is_hashable),
+            Object::Ok{value} | Object::Err{value} | Object::Some{value} => value.is_hashable(),
+            Object::None => true,
+            _ => false
+        })
+    }
+}
+
+/// The display form used by string interpolation: strings as their text,
+/// and strings inside arrays, hashes and variants quoted.
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        with_stack(|| match self {
+            Object::Integer{value} => write!(f, "{}", value),
+            Object::Boolean{value} => write!(f, "{}", value),
+            Object::String{value} => write!(f, "{}", value),
+            Object::Null => write!(f, "null"),
             Object::Array{elements} => {
-                if let Object::Array{elements: elements1} = other {
-                    return elements == elements1;
-                }
-                false
-            }
-            Object::Integer{value} => {
-                if let Object::Integer{value: v1} = other {
-                    return value == v1;
-                }
-                false
-            }
-            Object::Boolean{value} => {
-                if let Object::Boolean{value: v1} = other {
-                    return value == v1;
+                write!(f, "[")?;
+                for (idx, element) in elements.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ", ")?;
+                    }
+                    element.fmt_nested(f)?;
                 }
-                false
-            }
-            Object::String{value} => {
-                if let Object::String{value: v1} = other {
-                    return value == v1;
+                write!(f, "]")
+            },
+            Object::HashM{pairs} => {
+                write!(f, "{{")?;
+                for (idx, (key, value)) in pairs.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ", ")?;
+                    }
+                    key.fmt_nested(f)?;
+                    write!(f, ": ")?;
+                    value.fmt_nested(f)?;
                 }
-                false
-            }
-            _ => {
-                panic!("no impl for hash");
-            }
+                write!(f, "}}")
+            },
+            Object::Ok{value} | Object::Err{value} | Object::Some{value} => {
+                let name = match self {
+                    Object::Ok{..} => "Ok",
+                    Object::Err{..} => "Err",
+                    _ => "Some"
+                };
+                write!(f, "{}(", name)?;
+                value.fmt_nested(f)?;
+                write!(f, ")")
+            },
+            Object::None => write!(f, "None"),
+            Object::Return{value} => write!(f, "{}", value),
+            Object::Function{..} => write!(f, "<function>"),
+            Object::Builtin(_) => write!(f, "<builtin>"),
+            Object::Native(native) => write!(f, "{:?}", native),
+            Object::Error{message, ..} => write!(f, "error: {}", message)
+        })
+    }
+}
+
+impl Object {
+    /// Writes a value inside an array, hash or variant, quoting strings.
+    fn fmt_nested(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Object::String{value} => write!(f, "{:?}", value),
+            other => fmt::Display::fmt(other, f)
         }
     }
 }
 
+/// Consistent with `PartialEq`. A hash hashes only its size, so that the
+/// order its entries are stored in doesn't matter.
+impl Hash for Object {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        with_stack(|| {
+            self.type_name().hash(state);
+            match self {
+                Object::Integer{value} => value.hash(state),
+                Object::Boolean{value} => value.hash(state),
+                Object::String{value} => value.hash(state),
+                Object::Array{elements} => elements.hash(state),
+                Object::HashM{pairs} => pairs.len().hash(state),
+                Object::Native(native) => native.hash(state),
+                Object::Return{value} | Object::Ok{value} | Object::Err{value} | Object::Some{value} => value.hash(state),
+                Object::Function{body, ..} => Rc::as_ptr(body).hash(state),
+                Object::Builtin(builtin) => Rc::as_ptr(builtin).hash(state),
+                Object::Error{message, kind, stack} => {
+                    message.hash(state);
+                    kind.hash(state);
+                    stack.hash(state);
+                },
+                Object::None | Object::Null => {}
+            }
+        })
+    }
+}
+
+/// Values compare by structure, while functions and builtins are only
+/// equal to themselves.
+impl PartialEq for Object {
+    fn eq(&self, other: &Object) -> bool {
+        with_stack(|| match (self, other) {
+            (Object::Integer{value}, Object::Integer{value: other}) => value == other,
+            (Object::Boolean{value}, Object::Boolean{value: other}) => value == other,
+            (Object::String{value}, Object::String{value: other}) => value == other,
+            (Object::Array{elements}, Object::Array{elements: other}) => elements == other,
+            (Object::HashM{pairs}, Object::HashM{pairs: other}) => pairs == other,
+            (Object::Native(native), Object::Native(other)) => native == other,
+            (Object::Return{value}, Object::Return{value: other})
+            | (Object::Ok{value}, Object::Ok{value: other})
+            | (Object::Err{value}, Object::Err{value: other})
+            | (Object::Some{value}, Object::Some{value: other}) => value == other,
+            (Object::Function{parameters, body, environment},
+                Object::Function{parameters: other_parameters, body: other_body, environment: other_environment}) => {
+                Rc::ptr_eq(parameters, other_parameters) && Rc::ptr_eq(body, other_body)
+                    && Rc::ptr_eq(environment, other_environment)
+            },
+            (Object::Builtin(builtin), Object::Builtin(other)) => Rc::ptr_eq(builtin, other),
+            (Object::Error{message, kind, stack}, Object::Error{message: other_message, kind: other_kind, stack: other_stack}) => {
+                message == other_message && kind == other_kind && stack == other_stack
+            },
+            (Object::None, Object::None) | (Object::Null, Object::Null) => true,
+            _ => false
+        })
+    }
+}
+
 impl Eq for Object {}
\ No newline at end of file
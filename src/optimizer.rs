@@ -0,0 +1,171 @@
+use crate::ast::Expression;
+use crate::lexer::{Token, TokenType};
+use crate::visitor::{fold_expression_children, Fold};
+
+/// Folds operators whose operands are all literals, e.g. `60 * 60 * 24`
+/// becomes `86400` before the program is evaluated.
+///
+/// Only folds what evaluates the same at runtime: overflowing arithmetic
+/// and division by zero are left for the evaluator to deal with.
+pub struct ConstantFolder;
+
+impl Fold for ConstantFolder {
+    fn fold_expression(&mut self, expression: Expression) -> Expression {
+        match fold_expression_children(self, expression) {
+            Expression::PrefixExpression{token, operator, right} => {
+                match (operator.as_str(), right.as_ref()) {
+                    ("-", Expression::IntegerLiteral{value, ..}) if value.checked_neg().is_some() => {
+                        integer(&token, -value)
+                    },
+                    ("!", Expression::Boolean{value, ..}) => boolean(&token, !value),
+                    _ => Expression::PrefixExpression{token, operator, right}
+                }
+            },
+            Expression::InfixExpression{token, left, operator, right} => {
+                let folded = match (left.as_ref(), right.as_ref()) {
+                    (Expression::IntegerLiteral{value: l, ..}, Expression::IntegerLiteral{value: r, ..}) => {
+                        fold_integers(&token, &operator, *l, *r)
+                    },
+                    (Expression::Boolean{value: l, ..}, Expression::Boolean{value: r, ..}) => {
+                        match operator.as_str() {
+                            "==" => Some(boolean(&token, l == r)),
+                            "!=" => Some(boolean(&token, l != r)),
+                            _ => None
+                        }
+                    },
+                    (Expression::StringLiteral{value: l, ..}, Expression::StringLiteral{value: r, ..}) if operator == "+" => {
+                        Some(Expression::StringLiteral {
+                            token: literal_token(&token, TokenType::STRING, format!("{}{}", l, r)),
+                            value: format!("{}{}", l, r)
+                        })
+                    },
+                    _ => None
+                };
+                folded.unwrap_or(Expression::InfixExpression{token, left, operator, right})
+            },
+            expression => expression
+        }
+    }
+}
+
+fn fold_integers(token: &Token, operator: &str, left: i64, right: i64) -> Option<Expression> {
+    let value = match operator {
+        "+" => left.checked_add(right)?,
+        "-" => left.checked_sub(right)?,
+        "*" => left.checked_mul(right)?,
+        "/" => left.checked_div(right)?,
+        "<" => return Some(boolean(token, left < right)),
+        ">" => return Some(boolean(token, left > right)),
+        "<=" => return Some(boolean(token, left <= right)),
+        ">=" => return Some(boolean(token, left >= right)),
+        "==" => return Some(boolean(token, left == right)),
+        "!=" => return Some(boolean(token, left != right)),
+        _ => return None
+    };
+    Some(integer(token, value))
+}
+
+fn literal_token(token: &Token, token_type: TokenType, literal: String) -> Token {
+    let mut literal_token = Token::with_literal(token_type, literal);
+    literal_token.line = token.line;
+    literal_token.column = token.column;
+    literal_token
+}
+
+fn integer(token: &Token, value: i64) -> Expression {
+    Expression::IntegerLiteral {
+        token: literal_token(token, TokenType::INT, value.to_string()),
+        value
+    }
+}
+
+fn boolean(token: &Token, value: bool) -> Expression {
+    let token_type = if value { TokenType::TRUE } else { TokenType::FALSE };
+    Expression::Boolean {
+        token: literal_token(token, token_type, value.to_string()),
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ConstantFolder;
+    use crate::ast::{Expression, Program, Statement};
+    use crate::evaluator::Evaluator;
+    use crate::interpreter::Interpreter;
+    use crate::lexer::Lexer;
+    use crate::object::Object;
+    use crate::parser::Parser;
+    use crate::resolver::Resolver;
+    use crate::visitor::Fold;
+
+    fn parse(source: &str) -> Program {
+        Parser::new(Lexer::new(source.to_string())).parse_program().unwrap()
+    }
+
+    /// Evaluates `program` the way the interpreter does, minus the folding.
+    fn evaluate(program: Program) -> Object {
+        let environment = Interpreter::new().environment();
+        let program = Resolver::new(&environment.borrow()).resolve(program).unwrap();
+        program.evaluate(environment)
+    }
+
+    fn folded(source: &str) -> Expression {
+        match ConstantFolder.fold_program(parse(source)).statements.remove(0) {
+            Statement::ExpressionStatement{token:_, expression} => expression,
+            other => panic!("not an expression: {:?}", other)
+        }
+    }
+
+    #[test]
+    fn folding_does_not_change_results() {
+        for source in [
+            "60 * 60 * 24",
+            "1 + 2 * 3 - 4 / 2",
+            "-(2 - 5)",
+            "!true == false",
+            "2 * 3 < 7",
+            "\"a\" + \"b\" + \"c\"",
+            "let f = fn(x) { x + 2 * 3 }; f(1)",
+            "[1 + 1, {\"k\": 2 * 2}, if (1 < 2) { 3 - 3 } else { 0 }]",
+            "1 / 0",
+            "10 / (5 - 5)",
+            "9223372036854775807 + 1",
+            "-9223372036854775807 - 2",
+            "-(-9223372036854775807 - 1)",
+            "(-9223372036854775807 - 1) / -1",
+            "2 * 4611686018427387904"
+        ] {
+            assert_eq!(evaluate(ConstantFolder.fold_program(parse(source))), evaluate(parse(source)), "{}", source);
+        }
+    }
+
+    #[test]
+    fn folds_literal_operands() {
+        let cases = [
+            ("60 * 60 * 24", "86400"),
+            ("-(2 - 5)", "3"),
+            ("!(1 < 2)", "false"),
+            ("true != false", "true"),
+            ("\"a\" + \"b\"", "ab")
+        ];
+        for (source, expected) in cases {
+            let literal = match folded(source) {
+                Expression::IntegerLiteral{token, ..} | Expression::Boolean{token, ..} | Expression::StringLiteral{token, ..} => token.literal,
+                other => panic!("{} was not folded: {:?}", source, other)
+            };
+            assert_eq!(literal, expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn leaves_errors_to_the_evaluator() {
+        for source in ["1 / 0", "9223372036854775807 + 1", "(-9223372036854775807 - 1) / -1", "x + 1"] {
+            assert!(matches!(folded(source), Expression::InfixExpression{..}), "{}", source);
+        }
+        let mut interpreter = Interpreter::new();
+        assert_eq!(interpreter.eval_str("1 / 0").map_err(|err| err.to_string()), Err("runtime error: division by zero".to_string()));
+        assert_eq!(interpreter.eval_str("try { 9223372036854775807 + 1 } catch (e) { e[\"message\"] }"),
+            Ok(Object::new_string("integer overflow: 9223372036854775807 + 1".to_string())));
+    }
+}
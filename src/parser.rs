@@ -1,18 +1,21 @@
 
 
 use iota::iota;
-use std::{cell::RefCell, collections::HashMap};
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
-use ast::{Expression, Program, Statement};
+use ast::{Catch, Expression, MatchArm, Pattern, Program, Statement};
 
 use crate::lexer::{Lexer, Token, TokenType};
+use crate::visitor::with_stack;
 use crate::ast;
 
 pub type Error = String;
 
 iota! {
-    const BLANK: u32 = 1 << iota;
+    pub const BLANK: u32 = 1 << iota;
         , LOWEST
+        , LOGICALOR
+        , LOGICALAND
         , EQUALS
         , LESSGREATER
         , SUM
@@ -43,8 +46,10 @@ impl Parser {
         parser
     }
 
-    fn get_precedences() -> HashMap<TokenType, u32> {
+    pub fn get_precedences() -> HashMap<TokenType, u32> {
         let mut precedences  = HashMap::new();
+        precedences.insert(TokenType::OR, LOGICALOR);
+        precedences.insert(TokenType::AND, LOGICALAND);
         precedences.insert(TokenType::EQ, EQUALS);
         precedences.insert(TokenType::NOTEQ, EQUALS);
         precedences.insert(TokenType::LT, LESSGREATER);
@@ -57,19 +62,21 @@ impl Parser {
         precedences.insert(TokenType::SLASH, PRODUCT);
         precedences.insert(TokenType::LPAREN, CALL);
         precedences.insert(TokenType::LBRACKET, INDEX);
+        precedences.insert(TokenType::DOT, INDEX);
+        precedences.insert(TokenType::QUESTION, INDEX);
         precedences
     }
 
     fn peek_precedence(&self) -> u32 {
         if let Some(precedence) = self.precedences.get(&self.peek_token.token_type) {
-            return precedence.clone();
+            return *precedence;
         }
         LOWEST
     }
 
     fn cur_precedence(&self) -> u32 {
         if let Some(precedence) = self.precedences.get(&self.cur_token.token_type) {
-            return precedence.clone();
+            return *precedence;
         }
         LOWEST
     }
@@ -84,11 +91,15 @@ impl Parser {
             TokenType::FALSE => self.parse_boolean(),
             TokenType::LPAREN => self.parse_grouped_expression(),
             TokenType::IF => self.parse_if_expression(),
+            TokenType::MATCH => self.parse_match_expression(),
+            TokenType::TRY => self.parse_try_expression(),
             TokenType::FUNCTION => self.parse_function_literal(),
             TokenType::STRING => self.parse_string_literal(),
+            TokenType::TEMPLATESTART => self.parse_template_literal(),
             TokenType::LBRACKET => self.parse_array_literal(),
             TokenType::LBRACE => self.parse_hash_literal(),
-            _ => Err("prefix function not found".to_string())
+            TokenType::ELLIPSIS => self.parse_spread(),
+            _ => Err(error(&self.cur_token, "prefix function not found"))
         }
     }
 
@@ -104,8 +115,12 @@ impl Parser {
             TokenType::LTE => self.parse_infix_expression(left),
             TokenType::EQ => self.parse_infix_expression(left),
             TokenType::NOTEQ => self.parse_infix_expression(left),
+            TokenType::AND => self.parse_infix_expression(left),
+            TokenType::OR => self.parse_infix_expression(left),
             TokenType::LPAREN => self.parse_call_expression(left),
             TokenType::LBRACKET => self.parse_index_expression(left),
+            TokenType::DOT => self.parse_member_expression(left),
+            TokenType::QUESTION => self.parse_propagate(left),
 
             _ => Ok(left)
         }
@@ -120,17 +135,26 @@ impl Parser {
     pub fn parse_program(&mut self) -> Result<Program, Error> {
         let mut program = Program::new();
         while self.cur_token.token_type != TokenType::EOF {
-            let statement = self.parse_statement()?;
+            // A lexer error is usually what made the statement fail to parse.
+            let statement = self.parse_statement().map_err(|error| self.lexer_error().unwrap_or(error))?;
             program.statements.push(statement);
             self.next_token();
-        } 
-        Ok(program)
+        }
+        match self.lexer_error() {
+            Some(error) => Err(error),
+            None => Ok(program)
+        }
+    }
+
+    fn lexer_error(&self) -> Option<Error> {
+        self.lexer.errors().first().cloned()
     }
 
     fn parse_statement(&mut self) -> Result<Statement, Error> {
         match self.cur_token.token_type {
             TokenType::LET => self.parse_let_statement(),
             TokenType::RETURN => self.parse_return_statement(),
+            TokenType::THROW => self.parse_throw_statement(),
             _ => self.parse_expression_statement()
         }
     }
@@ -163,17 +187,17 @@ impl Parser {
     }
 
     fn parse_expression(&mut self, precedence: u32) -> Result<Expression, Error> {
-        let left_exp = self.call_prefix(self.cur_token.token_type)?;
-        let left_rc = RefCell::new(left_exp);
-        while !self.peek_token_is(TokenType::SEMICOLON) && precedence < self.peek_precedence() {
-            let infix_token = self.peek_token.token_type.clone();
-            self.next_token();
-            let left = left_rc.replace(Expression::Nope);
-            if let Ok(exp) = self.call_infix(infix_token, left) {
-                left_rc.replace(exp);
+        with_stack(|| {
+            let left_exp = self.call_prefix(self.cur_token.token_type)?;
+            let left_rc = RefCell::new(left_exp);
+            while !self.peek_token_is(TokenType::SEMICOLON) && precedence < self.peek_precedence() {
+                let infix_token = self.peek_token.token_type;
+                self.next_token();
+                let left = left_rc.replace(Expression::Nope);
+                left_rc.replace(self.call_infix(infix_token, left)?);
             }
-        }
-        return Ok(left_rc.into_inner());
+            Ok(left_rc.into_inner())
+        })
     }
 
     fn parse_prefix_expression(&mut self) -> Result<Expression, Error> {
@@ -219,27 +243,35 @@ impl Parser {
             return Ok(args);
         }
         self.next_token();
-        let arg = self.parse_expression(LOWEST)?;
+        let arg = self.parse_call_argument()?;
         args.push(arg);
         while self.peek_token_is(TokenType::COMA) {
             self.next_token();
             self.next_token();
-            let arg = self.parse_expression(LOWEST)?;
+            let after_keyword = matches!(args.last(), Some(Expression::Named{..}));
+            let start = self.cur_token.clone();
+            let arg = self.parse_call_argument()?;
+            if after_keyword && !matches!(arg, Expression::Named{..}) {
+                return Err(error(&start, "positional argument after keyword argument"));
+            }
             args.push(arg);
         }
-        if !self.expect_peek(TokenType::RPAREN) {
-            return Err("expceted ) after arguments".to_string());
-        }
+        self.expect(TokenType::RPAREN, "expceted ) after arguments")?;
         Ok(args)
     }
 
     fn parse_index_expression(&mut self, left: Expression) -> Result<Expression, Error> {
         let token = self.cur_token.clone();
         self.next_token();
+        if self.cur_token_is(TokenType::COLON) {
+            return self.parse_slice_expression(token, left, None);
+        }
         let index_exp = self.parse_expression(LOWEST)?;
-        if !self.expect_peek(TokenType::RBRACKET) {
-            return Err("array index should end with ]".to_string());
+        if self.peek_token_is(TokenType::COLON) {
+            self.next_token();
+            return self.parse_slice_expression(token, left, Some(Box::new(index_exp)));
         }
+        self.expect(TokenType::RBRACKET, "array index should end with ]")?;
         Ok(Expression::IndexExpression {
             token,
             left: Box::new(left),
@@ -247,35 +279,58 @@ impl Parser {
        })
     }
 
+    /// The rest of `left[start:end]`, from the `:`.
+    fn parse_slice_expression(&mut self, token: Token, left: Expression, start: Option<Box<Expression>>) -> Result<Expression, Error> {
+        let mut end = None;
+        if !self.peek_token_is(TokenType::RBRACKET) {
+            self.next_token();
+            end = Some(Box::new(self.parse_expression(LOWEST)?));
+        }
+        self.expect(TokenType::RBRACKET, "slice should end with ]")?;
+        Ok(Expression::SliceExpression {
+            token,
+            left: Box::new(left),
+            start,
+            end
+        })
+    }
+
+    fn parse_member_expression(&mut self, object: Expression) -> Result<Expression, Error> {
+        let token = self.cur_token.clone();
+        self.expect(TokenType::IDENT, "expected property name after .")?;
+        Ok(Expression::MemberExpression {
+            token,
+            object: Box::new(object),
+            property: self.cur_token.literal.clone()
+        })
+    }
+
+    fn parse_propagate(&mut self, value: Expression) -> Result<Expression, Error> {
+        Ok(Expression::Propagate {
+            token: self.cur_token.clone(),
+            value: Box::new(value)
+        })
+    }
+
     fn parse_grouped_expression(&mut self) -> Result<Expression, Error> {
         self.next_token();
         let exp = self.parse_expression(LOWEST)?;
-        if !self.expect_peek(TokenType::RPAREN) {
-            return Err("missing )".to_string());
-        }
+        self.expect(TokenType::RPAREN, "missing )")?;
         Ok(exp)
     }
 
     fn parse_if_expression(&mut self) -> Result<Expression, Error> {
         let token = self.cur_token.clone();
-        if !self.expect_peek(TokenType::LPAREN) {
-            return Err("expected ( after if".to_string());
-        }
+        self.expect(TokenType::LPAREN, "expected ( after if")?;
         self.next_token();
         let condition = self.parse_expression(LOWEST)?;
-        if !self.expect_peek(TokenType::RPAREN) {
-            return Err("expected ) after condition".to_string());
-        }
-        if !self.expect_peek(TokenType::LBRACE) {
-            return Err("expected { after if condition".to_string());
-        }
+        self.expect(TokenType::RPAREN, "expected ) after condition")?;
+        self.expect(TokenType::LBRACE, "expected { after if condition")?;
         let consequence = self.parse_block_statement()?;
 
         if self.peek_token_is(TokenType::ELSE) {
             self.next_token();
-            if !self.expect_peek(TokenType::LBRACE) {
-                return Err("expected { after else condition".to_string());
-            }
+            self.expect(TokenType::LBRACE, "expected { after else condition")?;
             let alternative = self.parse_block_statement()?;
             return Ok(Expression::IfExpression {
                 token,
@@ -294,49 +349,162 @@ impl Parser {
 
     }
 
-    fn parse_function_literal(&mut self) -> Result<Expression, Error> {
+    fn parse_try_expression(&mut self) -> Result<Expression, Error> {
         let token = self.cur_token.clone();
-        if !self.expect_peek(TokenType::LPAREN) {
-            return Err("expected ( after function".to_string());
+        self.expect(TokenType::LBRACE, "expected { after try")?;
+        let body = self.parse_block_statement()?;
+
+        let mut catch = None;
+        if self.peek_token_is(TokenType::CATCH) {
+            self.next_token();
+            let mut parameter = None;
+            if self.peek_token_is(TokenType::LPAREN) {
+                self.next_token();
+                self.next_token();
+                parameter = Some(self.parse_pattern()?);
+                self.expect(TokenType::RPAREN, "expected ) after catch parameter")?;
+            }
+            self.expect(TokenType::LBRACE, "expected { after catch")?;
+            let body = Box::new(self.parse_block_statement()?);
+            catch = Some(Box::new(Catch{parameter, body}));
         }
-        let parameters = self.parse_function_parameters()?;
-        if !self.expect_peek(TokenType::LBRACE) {
-            return Err("expected { before function body".to_string());
+
+        let mut finally = None;
+        if self.peek_token_is(TokenType::FINALLY) {
+            self.next_token();
+            self.expect(TokenType::LBRACE, "expected { after finally")?;
+            finally = Some(Box::new(self.parse_block_statement()?));
         }
+        if catch.is_none() && finally.is_none() {
+            return Err(error(&self.peek_token, "expected catch or finally after try block"));
+        }
+        Ok(Expression::TryExpression {
+            token,
+            body: Box::new(body),
+            catch,
+            finally
+        })
+    }
+
+    fn parse_match_expression(&mut self) -> Result<Expression, Error> {
+        let token = self.cur_token.clone();
+        self.expect(TokenType::LPAREN, "expected ( after match")?;
+        self.next_token();
+        let subject = self.parse_expression(LOWEST)?;
+        self.expect(TokenType::RPAREN, "expected ) after match subject")?;
+        self.expect(TokenType::LBRACE, "expected { after match subject")?;
+        let mut arms = Vec::new();
+        while !self.peek_token_is(TokenType::RBRACE) {
+            self.next_token();
+            let arm = self.parse_match_arm()?;
+            let block = matches!(*arm.body, Statement::BlockStatment{..});
+            arms.push(arm);
+            if !self.peek_token_is(TokenType::RBRACE) && !self.expect_peek(TokenType::COMA) && !block {
+                return Err(error(&self.peek_token, "expected , or } after match arm"));
+            }
+        }
+        self.next_token();
+        Ok(Expression::MatchExpression {
+            token,
+            subject: Box::new(subject),
+            arms
+        })
+    }
+
+    fn parse_match_arm(&mut self) -> Result<MatchArm, Error> {
+        let pattern = self.parse_pattern()?;
+        let mut guard = None;
+        if self.peek_token_is(TokenType::IF) {
+            self.next_token();
+            self.next_token();
+            guard = Some(self.parse_expression(LOWEST)?);
+        }
+        self.expect(TokenType::ARROW, "expected => after match pattern")?;
+        self.next_token();
+        let body = if self.cur_token_is(TokenType::LBRACE) {
+            self.parse_block_statement()?
+        } else {
+            let token = self.cur_token.clone();
+            let expression = self.parse_expression(LOWEST)?;
+            Statement::ExpressionStatement{token, expression}
+        };
+        Ok(MatchArm{pattern, guard, body: Box::new(body)})
+    }
+
+    fn parse_function_literal(&mut self) -> Result<Expression, Error> {
+        let token = self.cur_token.clone();
+        self.expect(TokenType::LPAREN, "expected ( after function")?;
+        let parameters = self.parse_function_parameters()?;
+        self.expect(TokenType::LBRACE, "expected { before function body")?;
         let body = self.parse_block_statement()?;
         Ok(Expression::FunctionLiteral{
             token,
             parameters,
-            body: Box::new(body)
+            body: Rc::new(body)
         })
     }
 
     fn parse_function_parameters(&mut self) -> Result<Vec<Expression>, Error> {
-        let mut identifiers = Vec::new();
+        let mut parameters = Vec::new();
         if self.peek_token_is(TokenType::RPAREN) {
             self.next_token();
-            return Ok(identifiers);
+            return Ok(parameters);
         }
         self.next_token();
-        let identifier = Expression::Identifier {
-            token: self.cur_token.clone(),
-            value: self.cur_token.literal.clone()
-        };
-        identifiers.push(identifier);
+        parameters.push(self.parse_function_parameter()?);
 
         while self.peek_token_is(TokenType::COMA) {
+            if let Some(Expression::Spread{..}) = parameters.last() {
+                return Err(error(&self.peek_token, "rest parameter must be the last parameter"));
+            }
             self.next_token();
             self.next_token();
-            let identifier = Expression::Identifier {
-                token: self.cur_token.clone(),
-                value: self.cur_token.literal.clone()
-            };
-            identifiers.push(identifier);
+            parameters.push(self.parse_function_parameter()?);
+        }
+        self.expect(TokenType::RPAREN, "expected ) after function parameters")?;
+        Ok(parameters)
+    }
+
+    /// `name`, `name = default` or `...rest`.
+    fn parse_function_parameter(&mut self) -> Result<Expression, Error> {
+        if self.cur_token_is(TokenType::ELLIPSIS) {
+            let token = self.cur_token.clone();
+            self.expect(TokenType::IDENT, "expected parameter name after ...")?;
+            let name = self.parse_identifier()?;
+            return Ok(Expression::Spread{token, value: Box::new(name)});
         }
-        if !self.expect_peek(TokenType::RPAREN) {
-            return Err("expected ) after function parameters".to_string());
+        if !self.cur_token_is(TokenType::IDENT) {
+            return Err(error(&self.cur_token, "expected parameter name"));
+        }
+        let name = self.parse_identifier()?;
+        if !self.peek_token_is(TokenType::ASSIGN) {
+            return Ok(name);
+        }
+        self.next_token();
+        let token = self.cur_token.clone();
+        self.next_token();
+        let value = self.parse_expression(LOWEST)?;
+        Ok(Expression::Named{token, name: Box::new(name), value: Box::new(value)})
+    }
+
+    /// An argument, or a keyword argument `name = value`.
+    fn parse_call_argument(&mut self) -> Result<Expression, Error> {
+        if !(self.cur_token_is(TokenType::IDENT) && self.peek_token_is(TokenType::ASSIGN)) {
+            return self.parse_expression(LOWEST);
         }
-        Ok(identifiers)
+        let name = self.parse_identifier()?;
+        self.next_token();
+        let token = self.cur_token.clone();
+        self.next_token();
+        let value = self.parse_expression(LOWEST)?;
+        Ok(Expression::Named{token, name: Box::new(name), value: Box::new(value)})
+    }
+
+    fn parse_spread(&mut self) -> Result<Expression, Error> {
+        let token = self.cur_token.clone();
+        self.next_token();
+        let value = self.parse_expression(PREFIX)?;
+        Ok(Expression::Spread{token, value: Box::new(value)})
     }
 
     fn parse_identifier(&mut self) -> Result<Expression, Error> {
@@ -351,7 +519,7 @@ impl Parser {
         let token = self.cur_token.clone();
         let number  = self.cur_token.literal.parse();
         if number.is_err() {
-            return Err("cannot parse string as number".to_string());
+            return Err(error(&self.cur_token, "cannot parse string as number"));
         }
         Ok(Expression::IntegerLiteral{
             token,
@@ -374,22 +542,18 @@ impl Parser {
         while !self.peek_token_is(TokenType::RBRACE) {
             self.next_token();
             let key = self.parse_expression(LOWEST)?;
-            if !self.expect_peek(TokenType::COLON) {
-                return Err("incorrect hashmap".to_string());
-            }
+            self.expect(TokenType::COLON, "incorrect hashmap")?;
             self.next_token();
             let value = self.parse_expression(LOWEST)?;
             pairs.push((key, value));
             if !self.peek_token_is(TokenType::RBRACE) && !self.peek_token_is(TokenType::COMA) {
-                return Err("missing , hashmap".to_string());
+                return Err(error(&self.peek_token, "missing , hashmap"));
             }
             if self.peek_token_is(TokenType::COMA) {
                 self.next_token();
             }
         }
-        if !self.expect_peek(TokenType::RBRACE) {
-            return Err("unednig hashmap".to_string());
-        }
+        self.expect(TokenType::RBRACE, "unednig hashmap")?;
         Ok(Expression::HashLiteral {
             pairs,
             token
@@ -411,9 +575,7 @@ impl Parser {
             let expression = self.parse_expression(LOWEST)?;
             result.push(expression);
         }
-        if !self.expect_peek(end) {
-            return Err("array must end with ]".to_string());
-        }
+        self.expect(end, "array must end with ]")?;
         Ok(result)
     }
 
@@ -425,6 +587,29 @@ impl Parser {
         })
     }
 
+    fn parse_template_literal(&mut self) -> Result<Expression, Error> {
+        let token = self.cur_token.clone();
+        let mut strings = vec![self.cur_token.literal.clone()];
+        let mut values = Vec::new();
+        while !self.cur_token_is(TokenType::TEMPLATEEND) {
+            if self.peek_token_is(TokenType::TEMPLATEMIDDLE) || self.peek_token_is(TokenType::TEMPLATEEND) {
+                return Err(error(&self.peek_token, "expected expression inside ${}"));
+            }
+            self.next_token();
+            values.push(self.parse_expression(LOWEST)?);
+            self.next_token();
+            if !self.cur_token_is(TokenType::TEMPLATEMIDDLE) && !self.cur_token_is(TokenType::TEMPLATEEND) {
+                return Err(error(&self.cur_token, "expected } after interpolated expression"));
+            }
+            strings.push(self.cur_token.literal.clone());
+        }
+        Ok(Expression::TemplateLiteral {
+            token,
+            strings,
+            values
+        })
+    }
+
     fn parse_boolean(&mut self) -> Result<Expression, Error> { 
         Ok(Expression::Boolean {
             token: self.cur_token.clone(),
@@ -435,29 +620,142 @@ impl Parser {
 
     fn parse_let_statement(&mut self) -> Result<Statement, Error> {
         let token = self.cur_token.clone();
-        if !self.expect_peek(TokenType::IDENT) {
-            Err("identifier expected".to_string())
-        } else {
-            let name = Expression::Identifier {
+        self.next_token();
+        let name = self.parse_pattern()?;
+        self.expect(TokenType::ASSIGN, "expected = after let pattern")?;
+        self.next_token();
+
+        let expression = self.parse_expression(LOWEST)?;
+
+        if self.peek_token_is(TokenType::SEMICOLON) {
+            self.next_token();
+        }
+        Ok(Statement::LetStatement {
+            token,
+            name,
+            value: expression
+        })
+    }
+
+    /// A pattern, or alternatives `a | b` that must all bind the same names.
+    fn parse_pattern(&mut self) -> Result<Pattern, Error> {
+        with_stack(|| {
+            let first = self.parse_single_pattern()?;
+            if !self.peek_token_is(TokenType::PIPE) {
+                return Ok(first);
+            }
+            let token = self.peek_token.clone();
+            let names = pattern_names(&first);
+            let mut alternatives = vec![first];
+            while self.peek_token_is(TokenType::PIPE) {
+                self.next_token();
+                self.next_token();
+                let start = self.cur_token.clone();
+                let alternative = self.parse_single_pattern()?;
+                if pattern_names(&alternative) != names {
+                    return Err(error(&start, "alternatives in a | pattern must bind the same names"));
+                }
+                alternatives.push(alternative);
+            }
+            Ok(Pattern::Or{token, alternatives})
+        })
+    }
+
+    /// `name`, `_`, a literal, `[a, b, ...rest]` or `{key, key: pattern}`.
+    fn parse_single_pattern(&mut self) -> Result<Pattern, Error> {
+        match self.cur_token.token_type {
+            TokenType::IDENT if self.cur_token.literal == "_" => Ok(Pattern::Wildcard(self.cur_token.clone())),
+            TokenType::IDENT if self.cur_token.literal == "None" => Ok(Pattern::Variant {
                 token: self.cur_token.clone(),
-                value: self.cur_token.clone().literal
-            };
-            if !self.expect_peek(TokenType::ASSIGN) {
-                return Err("identifier expected".to_string());
+                name: self.cur_token.literal.clone(),
+                value: None
+            }),
+            TokenType::IDENT if ["Ok", "Err", "Some"].contains(&self.cur_token.literal.as_str())
+                && self.peek_token_is(TokenType::LPAREN) => {
+                let token = self.cur_token.clone();
+                self.next_token();
+                self.next_token();
+                let value = self.parse_pattern()?;
+                self.expect(TokenType::RPAREN, &format!("expected ) after {} pattern", token.literal))?;
+                Ok(Pattern::Variant{name: token.literal.clone(), token, value: Some(Box::new(value))})
+            },
+            TokenType::IDENT => Ok(Pattern::Binding(self.parse_identifier()?)),
+            TokenType::INT => Ok(Pattern::Literal(self.parse_integer_literal()?)),
+            TokenType::STRING => Ok(Pattern::Literal(self.parse_string_literal()?)),
+            TokenType::TRUE | TokenType::FALSE => Ok(Pattern::Literal(self.parse_boolean()?)),
+            TokenType::MINUS if self.peek_token_is(TokenType::INT) => {
+                let token = self.cur_token.clone();
+                self.next_token();
+                let value: i64 = self.cur_token.literal.parse()
+                    .map_err(|_| "cannot parse string as number".to_string())?;
+                Ok(Pattern::Literal(Expression::IntegerLiteral{token, value: -value}))
+            },
+            TokenType::LBRACKET => self.parse_array_pattern(),
+            TokenType::LBRACE => self.parse_hash_pattern(),
+            _ => Err(error(&self.cur_token, &format!("expected identifier or pattern, got {}", self.cur_token.literal)))
+        }
+    }
+
+    fn parse_array_pattern(&mut self) -> Result<Pattern, Error> {
+        let token = self.cur_token.clone();
+        let mut elements = Vec::new();
+        let mut rest = None;
+        while !self.peek_token_is(TokenType::RBRACKET) {
+            if rest.is_some() {
+                return Err(error(&self.peek_token, "rest pattern must be the last element"));
             }
             self.next_token();
-            
-            let expression = self.parse_expression(LOWEST)?;
-            
-            if self.peek_token_is(TokenType::SEMICOLON) {
+            if self.cur_token_is(TokenType::ELLIPSIS) {
                 self.next_token();
+                rest = Some(Box::new(self.parse_pattern()?));
+            } else {
+                elements.push(self.parse_pattern()?);
             }
-            Ok(Statement::LetStatement {
-                token,
-                name,
-                value: expression
-            })
+            if !self.peek_token_is(TokenType::RBRACKET) && !self.expect_peek(TokenType::COMA) {
+                return Err(error(&self.peek_token, "expected , or ] in array pattern"));
+            }
+        }
+        self.next_token();
+        Ok(Pattern::Array{token, elements, rest})
+    }
+
+    fn parse_hash_pattern(&mut self) -> Result<Pattern, Error> {
+        let token = self.cur_token.clone();
+        let mut entries = Vec::new();
+        while !self.peek_token_is(TokenType::RBRACE) {
+            self.next_token();
+            let key = self.cur_token.literal.clone();
+            let pattern = match self.cur_token.token_type {
+                TokenType::IDENT if !self.peek_token_is(TokenType::COLON) => {
+                    Pattern::Binding(self.parse_identifier()?)
+                },
+                TokenType::IDENT | TokenType::STRING => {
+                    self.expect(TokenType::COLON, &format!("expected : after key {} in hash pattern", key))?;
+                    self.next_token();
+                    self.parse_pattern()?
+                },
+                _ => return Err(error(&self.cur_token, &format!("expected key in hash pattern, got {}", key)))
+            };
+            entries.push((key, pattern));
+            if !self.peek_token_is(TokenType::RBRACE) && !self.expect_peek(TokenType::COMA) {
+                return Err(error(&self.peek_token, "expected , or } in hash pattern"));
+            }
+        }
+        self.next_token();
+        Ok(Pattern::Hash{token, entries})
+    }
+
+    fn parse_throw_statement(&mut self) -> Result<Statement, Error> {
+        let token = self.cur_token.clone();
+        self.next_token();
+        let value = self.parse_expression(LOWEST)?;
+        if self.peek_token_is(TokenType::SEMICOLON) {
+            self.next_token();
         }
+        Ok(Statement::ThrowStatement {
+            token,
+            value
+        })
     }
 
     fn parse_return_statement(&mut self) -> Result<Statement, Error> {
@@ -481,11 +779,62 @@ impl Parser {
         self.peek_token.token_type == token_type
     }
 
+    /// Moves on to the next token if it is a `token_type`, and otherwise
+    /// fails with `message` at the token found instead.
+    fn expect(&mut self, token_type: TokenType, message: &str) -> Result<(), Error> {
+        if self.expect_peek(token_type) {
+            return Ok(());
+        }
+        Err(error(&self.peek_token, message))
+    }
+
     fn expect_peek(&mut self, token_type: TokenType) -> bool  {
         if self.peek_token_is(token_type) {
             self.next_token();
             return true;
         }
-        return false;
+        false
+    }
+}
+
+/// A parse error at `token`, prefixed with its `line:column` like the
+/// errors of the lexer and resolver.
+fn error(token: &Token, message: &str) -> Error {
+    format!("{}:{}: {}", token.line, token.column, message)
+}
+
+fn pattern_names(pattern: &Pattern) -> Vec<String> {
+    let mut names: Vec<String> = pattern.bindings().into_iter()
+        .filter_map(|name| match name {
+            Expression::Identifier{token:_, value} => Some(value.clone()),
+            _ => None
+        })
+        .collect();
+    names.sort_unstable();
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Parser;
+    use crate::lexer::Lexer;
+
+    #[test]
+    fn reports_where_parsing_failed() {
+        for (source, error) in [
+            ("let x = ;", "1:9: prefix function not found"),
+            ("let x = 1;\nlet y = (2 + 3;", "2:15: missing )"),
+            ("if (1 { 2 }", "1:7: expected ) after condition"),
+            ("f(a = 1, 2)", "1:10: positional argument after keyword argument"),
+            ("let f = fn(...r, a) { r };", "1:16: rest parameter must be the last parameter"),
+            ("let h = {1: 2 3};", "1:15: missing , hashmap"),
+            ("let [a, ...r, b] = [];", "1:15: rest pattern must be the last element"),
+            ("match (x) { 1 2 }", "1:15: expected => after match pattern"),
+            ("try { 1 }", "1:10: expected catch or finally after try block"),
+            ("let Some(a = 1;", "1:12: expected ) after Some pattern")
+        ] {
+            let mut parser = Parser::new(Lexer::new(source.to_string()));
+            assert_eq!(parser.parse_program().unwrap_err(), error, "{}", source);
+        }
     }
 }
@@ -0,0 +1,367 @@
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use crate::ast::{Catch, Expression, MatchArm, Pattern, Program, Statement};
+use crate::environment::Environment;
+use crate::lexer::Token;
+use crate::parser::Error;
+use crate::visitor::{fold_expression_children, fold_pattern_children, fold_statement_children, unwrap_body, walk_expression, Fold, Visitor};
+
+/// Binds every identifier to the scope it refers to before evaluation.
+///
+/// References to function parameters and locals are rewritten into
+/// `Expression::Local` so the evaluator can read them from a frame's slots
+/// instead of hashing the name at every access. Globals (top-level `let`s,
+/// builtins and anything already defined in the environment, e.g. in the
+/// REPL) keep being looked up by name.
+///
+/// Functions, `match` arms and `catch` blocks introduce scopes. A function's `let`s are
+/// visible to the closures in its whole body, which lets local closures call
+/// each other recursively, but the body itself only sees a local once its
+/// `let` has run. Before that, including in its own initializer, the name
+/// refers to the enclosing scopes, so `let c = c + 1` reads an outer `c`.
+/// An arm's pattern and `let`s are only visible in its guard and body, and
+/// a `catch` block's in that block.
+pub struct Resolver {
+    globals: HashSet<String>,
+    scopes: Vec<Scope>,
+    errors: Vec<Error>
+}
+
+#[derive(Default)]
+struct Scope {
+    /// The slot each name refers to from here on.
+    slots: HashMap<String, usize>,
+    /// Slots handed out so far.
+    count: usize,
+    /// Locals whose binding the resolver has already passed.
+    bound: HashSet<String>,
+    /// Whether this is a `match` arm or `catch` block rather than a
+    /// function.
+    block: bool
+}
+
+impl Resolver {
+    pub fn new(environment: &Environment) -> Self {
+        let mut globals = HashSet::new();
+        globals.extend(environment.store.keys().cloned());
+        let mut outer = environment.outer.clone();
+        while let Some(env) = outer {
+            globals.extend(env.borrow().store.keys().cloned());
+            outer = env.borrow().outer.clone();
+        }
+        Resolver {
+            globals,
+            scopes: Vec::new(),
+            errors: Vec::new()
+        }
+    }
+
+    pub fn resolve(mut self, program: Program) -> Result<Program, Vec<Error>> {
+        let mut names = LetNames::default();
+        names.visit_program(&program);
+        self.globals.extend(names.0.into_iter().map(|token| token.literal));
+
+        let program = self.fold_program(program);
+        if self.errors.is_empty() {
+            Ok(program)
+        } else {
+            Err(self.errors)
+        }
+    }
+
+    fn resolve_identifier(&mut self, expression: Expression) -> Expression {
+        if let Expression::Identifier{token, value} = expression {
+            // A closure may run after any of the lets around it.
+            let mut in_closure = false;
+            for (depth, scope) in self.scopes.iter().rev().enumerate() {
+                if let Some(slot) = scope.slots.get(&value) {
+                    if in_closure || scope.bound.contains(&value) {
+                        return Expression::Local{token, value, depth, slot: *slot};
+                    }
+                }
+                in_closure |= !scope.block;
+            }
+            if !self.globals.contains(&value) {
+                let mut declared_later = false;
+                for scope in self.scopes.iter().rev() {
+                    declared_later |= scope.slots.contains_key(&value);
+                    if !scope.block {
+                        break;
+                    }
+                }
+                let message = if declared_later {
+                    format!("variable `{}` is read before its `let`", value)
+                } else {
+                    format!("undefined variable `{}`", value)
+                };
+                self.errors.push(format!("{}:{}: {}", token.line, token.column, message));
+            }
+            return Expression::Identifier{token, value};
+        }
+        expression
+    }
+
+    /// Resolves an identifier a pattern binds, which is visible from then on.
+    fn bind(&mut self, expression: Expression) -> Expression {
+        if let (Expression::Identifier{token:_, value}, Some(scope)) = (&expression, self.scopes.last_mut()) {
+            scope.bound.insert(value.clone());
+        }
+        self.resolve_identifier(expression)
+    }
+
+    fn declare(&mut self, name: &str) -> bool {
+        let scope = self.scopes.last_mut().unwrap();
+        if scope.slots.contains_key(name) {
+            return false;
+        }
+        scope.slots.insert(name.to_string(), scope.count);
+        scope.count += 1;
+        true
+    }
+
+    /// Gives the names a `let` binds again in the same scope fresh slots, so
+    /// closures that captured the earlier binding keep seeing its value.
+    fn redeclare(&mut self, pattern: &Pattern) {
+        if let Some(scope) = self.scopes.last_mut() {
+            for binding in pattern.bindings() {
+                if let Expression::Identifier{token:_, value} = binding {
+                    if scope.bound.remove(value) {
+                        scope.slots.insert(value.clone(), scope.count);
+                        scope.count += 1;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Fold for Resolver {
+    fn fold_statement(&mut self, statement: Statement) -> Statement {
+        match statement {
+            // The initializer runs before the names are bound.
+            Statement::LetStatement{token, name, value} => {
+                let value = self.fold_expression(value);
+                self.redeclare(&name);
+                Statement::LetStatement{token, name: self.fold_pattern(name), value}
+            },
+            statement => fold_statement_children(self, statement)
+        }
+    }
+
+    fn fold_pattern(&mut self, pattern: Pattern) -> Pattern {
+        match pattern {
+            Pattern::Binding(name) => Pattern::Binding(self.bind(name)),
+            pattern => fold_pattern_children(self, pattern)
+        }
+    }
+
+    fn fold_expression(&mut self, expression: Expression) -> Expression {
+        match expression {
+            Expression::Identifier{..} => self.resolve_identifier(expression),
+            Expression::FunctionLiteral{token, parameters, body} => {
+                self.scopes.push(Scope::default());
+                for parameter in &parameters {
+                    if let Expression::Identifier{token, value} = parameter.parameter_name() {
+                        if !self.declare(value) {
+                            self.errors.push(format!("{}:{}: duplicate parameter `{}`", token.line, token.column, value));
+                        }
+                        self.scopes.last_mut().unwrap().bound.insert(value.clone());
+                    }
+                }
+                let mut names = LetNames::default();
+                names.visit_statement(&body);
+                for token in names.0 {
+                    self.declare(&token.literal);
+                }
+
+                let parameters = parameters.into_iter().map(|parameter| match parameter {
+                    Expression::Named{token, name, value} => Expression::Named {
+                        token,
+                        name: Box::new(self.resolve_identifier(*name)),
+                        value: Box::new(self.fold_expression(*value))
+                    },
+                    Expression::Spread{token, value} => Expression::Spread {
+                        token,
+                        value: Box::new(self.resolve_identifier(*value))
+                    },
+                    parameter => self.resolve_identifier(parameter)
+                }).collect();
+                let body = Rc::new(self.fold_statement(unwrap_body(body)));
+                self.scopes.pop();
+                Expression::FunctionLiteral{token, parameters, body}
+            },
+            Expression::MatchExpression{token, subject, arms} => Expression::MatchExpression {
+                token,
+                subject: Box::new(self.fold_expression(*subject)),
+                arms: arms.into_iter().map(|arm| {
+                    self.scopes.push(Scope{block: true, ..Scope::default()});
+                    let mut names = LetNames::default();
+                    names.visit_arm(&arm);
+                    for token in names.0 {
+                        self.declare(&token.literal);
+                    }
+                    let arm = MatchArm {
+                        pattern: self.fold_pattern(arm.pattern),
+                        guard: arm.guard.map(|guard| self.fold_expression(guard)),
+                        body: Box::new(self.fold_statement(*arm.body))
+                    };
+                    self.scopes.pop();
+                    arm
+                }).collect()
+            },
+            Expression::TryExpression{token, body, catch, finally} => Expression::TryExpression {
+                token,
+                body: Box::new(self.fold_statement(*body)),
+                catch: catch.map(|catch| {
+                    self.scopes.push(Scope{block: true, ..Scope::default()});
+                    let mut names = LetNames::default();
+                    names.visit_catch(&catch);
+                    for token in names.0 {
+                        self.declare(&token.literal);
+                    }
+                    let catch = Box::new(Catch {
+                        parameter: catch.parameter.map(|parameter| self.fold_pattern(parameter)),
+                        body: Box::new(self.fold_statement(*catch.body))
+                    });
+                    self.scopes.pop();
+                    catch
+                }),
+                finally: finally.map(|finally| Box::new(self.fold_statement(*finally)))
+            },
+            expression => fold_expression_children(self, expression)
+        }
+    }
+}
+
+/// Identifier tokens bound by `let` in a scope, not counting nested
+/// functions, `match` arms and `catch` blocks.
+#[derive(Default)]
+pub(crate) struct LetNames(pub Vec<Token>);
+
+impl LetNames {
+    /// Collects the names in the scope of a `match` arm: its pattern's and
+    /// those of the `let`s in its guard and body.
+    pub(crate) fn visit_arm(&mut self, arm: &MatchArm) {
+        self.visit_pattern(&arm.pattern);
+        if let Some(guard) = &arm.guard {
+            self.visit_expression(guard);
+        }
+        self.visit_statement(&arm.body);
+    }
+
+    /// Collects the names in the scope of a `catch` block: its parameter's
+    /// and those of the `let`s in the block.
+    pub(crate) fn visit_catch(&mut self, catch: &Catch) {
+        if let Some(parameter) = &catch.parameter {
+            self.visit_pattern(parameter);
+        }
+        self.visit_statement(&catch.body);
+    }
+}
+
+impl Visitor for LetNames {
+    fn visit_pattern(&mut self, pattern: &Pattern) {
+        for binding in pattern.bindings() {
+            if let Expression::Identifier{token, value:_} = binding {
+                self.0.push(token.clone());
+            }
+        }
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::FunctionLiteral{..} => {},
+            Expression::MatchExpression{token:_, subject, arms:_} => self.visit_expression(subject),
+            Expression::TryExpression{token:_, body, catch:_, finally} => {
+                self.visit_statement(body);
+                if let Some(finally) = finally {
+                    self.visit_statement(finally);
+                }
+            },
+            _ => walk_expression(self, expression)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::{Error, Interpreter};
+    use crate::object::Object;
+
+    fn eval(source: &str) -> Result<Object, Error> {
+        Interpreter::new().eval_str(source)
+    }
+
+    fn ints(values: &[i64]) -> Object {
+        Object::Array{elements: values.iter().map(|value| Object::new_int(*value)).collect()}
+    }
+
+    #[test]
+    fn initializer_reads_the_outer_binding() {
+        let source = "let counter = fn() { let c = 0; fn() { let c = c + 1; c } };
+            let k = counter();
+            [k(), k()]";
+        assert_eq!(eval(source), Ok(ints(&[1, 1])));
+        assert_eq!(eval("let f = fn(x) { let x = x * 2; x }; f(3)"), Ok(Object::new_int(6)));
+    }
+
+    #[test]
+    fn local_closures_see_later_lets() {
+        let source = "let f = fn() {
+                let even = fn(n) { if (n == 0) { true } else { odd(n - 1) } };
+                let odd = fn(n) { if (n == 0) { false } else { even(n - 1) } };
+                [even(10), odd(10)]
+            };
+            f()";
+        assert_eq!(eval(source), Ok(Object::Array{elements: vec![Object::new_bool(true), Object::new_bool(false)]}));
+    }
+
+    #[test]
+    fn redeclared_lets_leave_captured_bindings_alone() {
+        assert_eq!(eval("let f = fn() { let x = 1; let g = fn() { x }; let x = 2; [g(), x] }; f()"), Ok(ints(&[1, 2])));
+        assert_eq!(eval("let f = fn(x) { let g = fn() { x }; let x = x * 2; [g(), x] }; f(3)"), Ok(ints(&[3, 6])));
+        assert_eq!(eval("let f = fn(v) { let [a, b] = v; let [b, a] = [a, b]; [a, b] }; f([1, 2])"), Ok(ints(&[2, 1])));
+    }
+
+    #[test]
+    fn reports_reads_before_let() {
+        assert_eq!(eval("let f = fn() { let y = y + 1; y };"),
+            Err(Error::Resolve(vec!["1:24: variable `y` is read before its `let`".to_string()])));
+        assert_eq!(eval("let f = fn() { z };"), Err(Error::Resolve(vec!["1:16: undefined variable `z`".to_string()])));
+    }
+
+    #[test]
+    fn binds_pattern_names() {
+        let source = "let f = fn(v) { match (v) { [a, b] if a > b => a - b, n => n } }; [f([3, 1]), f(7)]";
+        assert_eq!(eval(source), Ok(ints(&[2, 7])));
+        assert_eq!(eval("let f = fn() { let {a, b: [c]} = {\"a\": 1, \"b\": [2]}; a + c }; f()"), Ok(Object::new_int(3)));
+    }
+
+    #[test]
+    fn match_arms_bind_in_their_own_scope() {
+        let cases = [
+            ("let f = fn(n) { match (5) { n => n }; n }; f(1)", 1),
+            ("let n = 3; match (5) { n => n }; n", 3),
+            ("let f = fn() { let n = 3; match (5) { n if n > 9 => 0, m => { let n = m * 2; n } } + n }; f()", 13),
+            ("let f = fn(v) { match (v) { [a] => fn() { a }, a => fn() { a } } }; f([7])() + f(8)()", 15)
+        ];
+        for (source, expected) in cases.iter().copied() {
+            assert_eq!(eval(source), Ok(Object::new_int(expected)), "{}", source);
+        }
+        assert_eq!(eval("match (1) { a => a }; a"), Err(Error::Resolve(vec!["1:23: undefined variable `a`".to_string()])));
+    }
+
+    #[test]
+    fn catch_binds_in_its_own_scope() {
+        let cases = [
+            ("let f = fn(e) { try { throw \"x\" } catch (e) { 0 }; e }; f(1)", 1),
+            ("let e = 2; try { throw \"x\" } catch (e) { 0 }; e", 2),
+            ("let f = fn() { let n = 3; try { throw \"x\" } catch ({message}) { let n = len(message); n } + n }; f()", 4)
+        ];
+        for (source, expected) in cases.iter().copied() {
+            assert_eq!(eval(source), Ok(Object::new_int(expected)), "{}", source);
+        }
+        assert_eq!(eval("try { throw 1 } catch (e) { 0 }; e"), Err(Error::Resolve(vec!["1:34: undefined variable `e`".to_string()])));
+    }
+}
@@ -0,0 +1,681 @@
+//! Conversion between Monkey values and serde data, enabled by the `serde`
+//! feature.
+//!
+//! Integers, booleans, strings, arrays, hashes and `null` map onto the serde
+//! data model, `Some` and `None` onto options and `Ok` and `Err` onto
+//! `Result`; functions, builtins, native values and errors are not data
+//! and fail to convert. Monkey has no floats, so floats fail as well.
+//!
+//! ```
+//! use std::collections::HashMap;
+//! use monkey::{serialization::{from_object, to_object}, Interpreter};
+//!
+//! let mut limits = HashMap::new();
+//! limits.insert("retries".to_string(), vec![1, 2, 4]);
+//! let mut interpreter = Interpreter::new();
+//! interpreter.set_global("limits", to_object(&limits).unwrap());
+//! let result = interpreter.eval_str("push(limits[\"retries\"], 8)").unwrap();
+//! let retries: Vec<u32> = from_object(result).unwrap();
+//! assert_eq!(retries, vec![1, 2, 4, 8]);
+//! ```
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+use serde::de::{self, Deserialize, DeserializeSeed, Deserializer, IntoDeserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{self, Serialize, SerializeMap, SerializeSeq, Serializer};
+use serde::forward_to_deserialize_any;
+
+use crate::object::Object;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+/// Converts any serializable Rust value into a Monkey value.
+pub fn to_object<T: Serialize + ?Sized>(value: &T) -> Result<Object, Error> {
+    value.serialize(ObjectSerializer)
+}
+
+/// Converts a Monkey value into any deserializable Rust value.
+pub fn from_object<'de, T: Deserialize<'de>>(object: Object) -> Result<T, Error> {
+    T::deserialize(object)
+}
+
+fn not_data(object: &Object) -> String {
+    format!("cannot convert {} to data", object.type_name())
+}
+
+impl Serialize for Object {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Object::Integer{value} => serializer.serialize_i64(*value),
+            Object::Boolean{value} => serializer.serialize_bool(*value),
+            Object::String{value} => serializer.serialize_str(value),
+            Object::Null => serializer.serialize_unit(),
+            Object::None => serializer.serialize_none(),
+            Object::Some{value} => serializer.serialize_some(value),
+            Object::Ok{value} => serializer.serialize_newtype_variant("Result", 0, "Ok", value),
+            Object::Err{value} => serializer.serialize_newtype_variant("Result", 1, "Err", value),
+            Object::Array{elements} => {
+                let mut seq = serializer.serialize_seq(Some(elements.len()))?;
+                for element in elements {
+                    seq.serialize_element(element)?;
+                }
+                seq.end()
+            },
+            Object::HashM{pairs} => {
+                let mut map = serializer.serialize_map(Some(pairs.len()))?;
+                for (key, value) in pairs {
+                    map.serialize_entry(key, value)?;
+                }
+                map.end()
+            },
+            other => Err(ser::Error::custom(not_data(other)))
+        }
+    }
+}
+
+struct ObjectVisitor;
+
+impl<'de> Visitor<'de> for ObjectVisitor {
+    type Value = Object;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "an integer, boolean, string, null, array or map")
+    }
+
+    fn visit_bool<E: de::Error>(self, value: bool) -> Result<Object, E> {
+        Ok(Object::new_bool(value))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Object, E> {
+        Ok(Object::new_int(value))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Object, E> {
+        i64::try_from(value)
+            .map(Object::new_int)
+            .map_err(|_| E::custom(format!("integer {} out of range", value)))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Object, E> {
+        Ok(Object::new_string(value.to_string()))
+    }
+
+    fn visit_string<E: de::Error>(self, value: String) -> Result<Object, E> {
+        Ok(Object::new_string(value))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Object, E> {
+        Ok(Object::Null)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Object, E> {
+        Ok(Object::None)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Object, D::Error> {
+        Ok(Object::Some{value: Box::new(Object::deserialize(deserializer)?)})
+    }
+
+    /// `Ok` and `Err` are the Monkey variants; other enums with contents
+    /// become a one-entry hash, as when serializing.
+    fn visit_enum<A: de::EnumAccess<'de>>(self, data: A) -> Result<Object, A::Error> {
+        let (name, contents): (String, _) = data.variant()?;
+        let value = de::VariantAccess::newtype_variant::<Object>(contents)?;
+        Ok(match name.as_str() {
+            "Ok" => Object::Ok{value: Box::new(value)},
+            "Err" => Object::Err{value: Box::new(value)},
+            _ => variant(&name, value)
+        })
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Object, A::Error> {
+        let mut elements = Vec::new();
+        while let Some(element) = seq.next_element()? {
+            elements.push(element);
+        }
+        Ok(Object::Array{elements})
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Object, A::Error> {
+        let mut pairs = HashMap::new();
+        while let Some((key, value)) = map.next_entry::<Object, Object>()? {
+            if !key.is_hashable() {
+                return Err(de::Error::custom(format!("unusable as hash key: {}", key.type_name())));
+            }
+            pairs.insert(key, value);
+        }
+        Ok(Object::HashM{pairs})
+    }
+}
+
+impl<'de> Deserialize<'de> for Object {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ObjectVisitor)
+    }
+}
+
+/// Reading Rust data out of a Monkey value. `None` and `null` are `None`,
+/// `null` is also unit, and `Ok` and `Err` are the variants of `Result`.
+/// Other enums are a string for unit variants or a one-entry hash
+/// otherwise.
+impl<'de> Deserializer<'de> for Object {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, Error> {
+        match &mut self {
+            Object::Integer{value} => visitor.visit_i64(*value),
+            Object::Boolean{value} => visitor.visit_bool(*value),
+            Object::String{value} => visitor.visit_string(std::mem::take(value)),
+            Object::Null => visitor.visit_unit(),
+            Object::None => visitor.visit_none(),
+            Object::Some{..} => visitor.visit_some(self.take_inner().unwrap_or_default()),
+            Object::Ok{..} | Object::Err{..} => visitor.visit_enum(result_access(self)),
+            Object::Array{elements} => {
+                visitor.visit_seq(de::value::SeqDeserializer::new(std::mem::take(elements).into_iter()))
+            },
+            Object::HashM{pairs} => {
+                visitor.visit_map(de::value::MapDeserializer::new(std::mem::take(pairs).into_iter()))
+            },
+            other => Err(Error(not_data(other)))
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            Object::Null | Object::None => visitor.visit_none(),
+            mut object @ Object::Some{..} => visitor.visit_some(object.take_inner().unwrap_or_default()),
+            other => visitor.visit_some(other)
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(mut self, _name: &'static str, _variants: &'static [&'static str],
+        visitor: V) -> Result<V::Value, Error> {
+        match &mut self {
+            Object::Ok{..} | Object::Err{..} => visitor.visit_enum(result_access(self)),
+            Object::String{value} => visitor.visit_enum(std::mem::take(value).into_deserializer()),
+            Object::HashM{pairs} if pairs.len() == 1 => {
+                let (variant, value) = pairs.drain().next().unwrap();
+                visitor.visit_enum(EnumAccess{variant, value})
+            },
+            other => Err(Error(format!("expected a string or a hash with one entry for an enum, got {}",
+                other.type_name())))
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V)
+        -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+impl<'de> IntoDeserializer<'de, Error> for Object {
+    type Deserializer = Object;
+
+    fn into_deserializer(self) -> Object {
+        self
+    }
+}
+
+struct EnumAccess {
+    variant: Object,
+    value: Object
+}
+
+/// `Ok(value)` or `Err(value)` as the variant of a `Result`.
+fn result_access(mut object: Object) -> EnumAccess {
+    let name = if let Object::Ok{..} = object { "Ok" } else { "Err" };
+    EnumAccess {
+        variant: Object::new_string(name.to_string()),
+        value: object.take_inner().unwrap_or_default()
+    }
+}
+
+impl<'de> de::EnumAccess<'de> for EnumAccess {
+    type Error = Error;
+    type Variant = Object;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Object), Error> {
+        Ok((seed.deserialize(self.variant)?, self.value))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Object {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        Deserialize::deserialize(self)
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_any(visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, _fields: &'static [&'static str], visitor: V)
+        -> Result<V::Value, Error> {
+        self.deserialize_any(visitor)
+    }
+}
+
+/// Writing Rust data as a Monkey value. Unit variants become strings and
+/// other variants a one-entry hash from the variant name to its contents.
+struct ObjectSerializer;
+
+fn variant(name: &str, value: Object) -> Object {
+    let mut pairs = HashMap::new();
+    pairs.insert(Object::new_string(name.to_string()), value);
+    Object::HashM{pairs}
+}
+
+impl Serializer for ObjectSerializer {
+    type Ok = Object;
+    type Error = Error;
+    type SerializeSeq = SeqSerializer;
+    type SerializeTuple = SeqSerializer;
+    type SerializeTupleStruct = SeqSerializer;
+    type SerializeTupleVariant = SeqSerializer;
+    type SerializeMap = MapSerializer;
+    type SerializeStruct = MapSerializer;
+    type SerializeStructVariant = MapSerializer;
+
+    fn serialize_bool(self, value: bool) -> Result<Object, Error> {
+        Ok(Object::new_bool(value))
+    }
+
+    fn serialize_i8(self, value: i8) -> Result<Object, Error> {
+        self.serialize_i64(value.into())
+    }
+
+    fn serialize_i16(self, value: i16) -> Result<Object, Error> {
+        self.serialize_i64(value.into())
+    }
+
+    fn serialize_i32(self, value: i32) -> Result<Object, Error> {
+        self.serialize_i64(value.into())
+    }
+
+    fn serialize_i64(self, value: i64) -> Result<Object, Error> {
+        Ok(Object::new_int(value))
+    }
+
+    fn serialize_u8(self, value: u8) -> Result<Object, Error> {
+        self.serialize_i64(value.into())
+    }
+
+    fn serialize_u16(self, value: u16) -> Result<Object, Error> {
+        self.serialize_i64(value.into())
+    }
+
+    fn serialize_u32(self, value: u32) -> Result<Object, Error> {
+        self.serialize_i64(value.into())
+    }
+
+    fn serialize_u64(self, value: u64) -> Result<Object, Error> {
+        i64::try_from(value)
+            .map(Object::new_int)
+            .map_err(|_| Error(format!("integer {} out of range", value)))
+    }
+
+    fn serialize_f32(self, value: f32) -> Result<Object, Error> {
+        self.serialize_f64(value.into())
+    }
+
+    fn serialize_f64(self, value: f64) -> Result<Object, Error> {
+        Err(Error(format!("cannot convert float {} to a Monkey value", value)))
+    }
+
+    fn serialize_char(self, value: char) -> Result<Object, Error> {
+        Ok(Object::new_string(value.to_string()))
+    }
+
+    fn serialize_str(self, value: &str) -> Result<Object, Error> {
+        Ok(Object::new_string(value.to_string()))
+    }
+
+    fn serialize_bytes(self, value: &[u8]) -> Result<Object, Error> {
+        Ok(Object::Array {
+            elements: value.iter().map(|byte| Object::new_int((*byte).into())).collect()
+        })
+    }
+
+    fn serialize_none(self) -> Result<Object, Error> {
+        Ok(Object::None)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Object, Error> {
+        Ok(Object::Some{value: Box::new(value.serialize(self)?)})
+    }
+
+    fn serialize_unit(self) -> Result<Object, Error> {
+        Ok(Object::Null)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Object, Error> {
+        Ok(Object::Null)
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, variant: &'static str)
+        -> Result<Object, Error> {
+        Ok(Object::new_string(variant.to_string()))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T)
+        -> Result<Object, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(self, enum_name: &'static str, _index: u32,
+        name: &'static str, value: &T) -> Result<Object, Error> {
+        let value = Box::new(value.serialize(self)?);
+        Ok(match (enum_name, name) {
+            ("Result", "Ok") => Object::Ok{value},
+            ("Result", "Err") => Object::Err{value},
+            _ => variant(name, *value)
+        })
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SeqSerializer, Error> {
+        Ok(SeqSerializer {
+            variant: None,
+            elements: Vec::with_capacity(len.unwrap_or(0))
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SeqSerializer, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<SeqSerializer, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(self, _name: &'static str, _index: u32, name: &'static str, len: usize)
+        -> Result<SeqSerializer, Error> {
+        Ok(SeqSerializer {
+            variant: Some(name),
+            elements: Vec::with_capacity(len)
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<MapSerializer, Error> {
+        Ok(MapSerializer {
+            variant: None,
+            pairs: HashMap::new(),
+            key: None
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<MapSerializer, Error> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(self, _name: &'static str, _index: u32, name: &'static str, _len: usize)
+        -> Result<MapSerializer, Error> {
+        Ok(MapSerializer {
+            variant: Some(name),
+            pairs: HashMap::new(),
+            key: None
+        })
+    }
+}
+
+struct SeqSerializer {
+    variant: Option<&'static str>,
+    elements: Vec<Object>
+}
+
+impl SerializeSeq for SeqSerializer {
+    type Ok = Object;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.elements.push(value.serialize(ObjectSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Object, Error> {
+        let array = Object::Array{elements: self.elements};
+        Ok(match self.variant {
+            Some(name) => variant(name, array),
+            None => array
+        })
+    }
+}
+
+impl ser::SerializeTuple for SeqSerializer {
+    type Ok = Object;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Object, Error> {
+        SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SeqSerializer {
+    type Ok = Object;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Object, Error> {
+        SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleVariant for SeqSerializer {
+    type Ok = Object;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Object, Error> {
+        SerializeSeq::end(self)
+    }
+}
+
+struct MapSerializer {
+    variant: Option<&'static str>,
+    pairs: HashMap<Object, Object>,
+    key: Option<Object>
+}
+
+impl SerializeMap for MapSerializer {
+    type Ok = Object;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        let key = key.serialize(ObjectSerializer)?;
+        if !key.is_hashable() {
+            return Err(Error(format!("unusable as hash key: {}", key.type_name())));
+        }
+        self.key = Some(key);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let key = self.key.take().expect("serialize_value called before serialize_key");
+        self.pairs.insert(key, value.serialize(ObjectSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Object, Error> {
+        let hash = Object::HashM{pairs: self.pairs};
+        Ok(match self.variant {
+            Some(name) => variant(name, hash),
+            None => hash
+        })
+    }
+}
+
+impl ser::SerializeStruct for MapSerializer {
+    type Ok = Object;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), Error> {
+        self.pairs.insert(Object::new_string(key.to_string()), value.serialize(ObjectSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Object, Error> {
+        SerializeMap::end(self)
+    }
+}
+
+impl ser::SerializeStructVariant for MapSerializer {
+    type Ok = Object;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), Error> {
+        ser::SerializeStruct::serialize_field(self, key, value)
+    }
+
+    fn end(self) -> Result<Object, Error> {
+        SerializeMap::end(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde::{Deserialize, Serialize};
+
+    use super::{from_object, to_object, Error};
+    use crate::interpreter::Interpreter;
+    use crate::object::Object;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Config {
+        name: String,
+        retries: Vec<u32>,
+        weights: HashMap<String, i64>,
+        modes: Vec<Mode>,
+        parent: Option<Box<Config>>
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    enum Mode {
+        Fast,
+        Slow(u32),
+        Pair(i64, i64),
+        Custom { level: u8 }
+    }
+
+    fn config() -> Config {
+        let mut weights = HashMap::new();
+        weights.insert("a".to_string(), 1);
+        weights.insert("b".to_string(), -2);
+        Config {
+            name: "child".to_string(),
+            retries: vec![1, 2, 4],
+            weights,
+            modes: vec![Mode::Fast, Mode::Slow(3), Mode::Pair(1, 2), Mode::Custom { level: 9 }],
+            parent: Some(Box::new(Config {
+                name: "parent".to_string(),
+                retries: vec![],
+                weights: HashMap::new(),
+                modes: vec![],
+                parent: None
+            }))
+        }
+    }
+
+    #[test]
+    fn options_and_results_are_monkey_variants() {
+        let values: Vec<Option<Result<i64, String>>> = vec![Some(Ok(1)), Some(Err("no".to_string())), None];
+        let object = to_object(&values).unwrap();
+        assert_eq!(object.to_string(), "[Some(Ok(1)), Some(Err(\"no\")), None]");
+        assert_eq!(from_object::<Vec<Option<Result<i64, String>>>>(object.clone()), Ok(values));
+        assert_eq!(from_object::<Object>(object.clone()), Ok(object));
+
+        assert_eq!(from_object::<Option<i64>>(Object::None), Ok(None));
+        assert_eq!(from_object::<Option<i64>>(Object::Null), Ok(None));
+        assert_eq!(from_object::<Option<i64>>(Object::new_int(3)), Ok(Some(3)));
+    }
+
+    #[test]
+    fn round_trips_nested_structs_enums_and_maps() {
+        let object = to_object(&config()).unwrap();
+        assert_eq!(from_object::<Config>(object.clone()), Ok(config()));
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_global("config", object);
+        let cases = [
+            ("config[\"retries\"]", "[1, 2, 4]"),
+            ("config[\"weights\"][\"b\"]", "-2"),
+            ("config[\"modes\"]", "[\"Fast\", {\"Slow\": 3}, {\"Pair\": [1, 2]}, {\"Custom\": {\"level\": 9}}]"),
+            ("match (config[\"parent\"]) { Some(parent) => parent[\"name\"] }", "parent"),
+            ("match (config[\"parent\"]) { Some(parent) => parent[\"parent\"] }", "None")
+        ];
+        for (source, expected) in cases {
+            assert_eq!(interpreter.eval_str(source).map(|r| r.to_string()), Ok(expected.to_string()), "{}", source);
+        }
+
+        let edited = interpreter.eval_str("let modes = push(config[\"modes\"], {\"Slow\": 5}); modes").unwrap();
+        let mut modes = config().modes;
+        modes.push(Mode::Slow(5));
+        assert_eq!(from_object::<Vec<Mode>>(edited), Ok(modes));
+    }
+
+    #[test]
+    fn rejects_values_that_are_not_data() {
+        let mut interpreter = Interpreter::new();
+        let function = interpreter.eval_str("fn(x) { x }").unwrap();
+        let len = interpreter.get_global("len").unwrap();
+        let error = |message: &str| Error(message.to_string());
+
+        assert_eq!(from_object::<i64>(function.clone()), Err(error("cannot convert FUNCTION to data")));
+        assert_eq!(to_object(&Object::Array{elements: vec![Object::new_int(1), len]}), Err(error("cannot convert BUILTIN to data")));
+        assert_eq!(from_object::<Object>(Object::new_error("boom".to_string())), Err(error("cannot convert ERROR to data")));
+        assert_eq!(to_object(&1.5), Err(error("cannot convert float 1.5 to a Monkey value")));
+        assert_eq!(from_object::<u8>(Object::new_int(300)).map_err(|err| err.to_string()),
+            Err("invalid value: integer `300`, expected u8".to_string()));
+        assert_eq!(from_object::<Mode>(Object::new_int(1)),
+            Err(error("expected a string or a hash with one entry for an enum, got INTEGER")));
+        assert_eq!(from_object::<Mode>(Object::new_string("Slower".to_string())).map_err(|err| err.to_string()),
+            Err("unknown variant `Slower`, expected one of `Fast`, `Slow`, `Pair`, `Custom`".to_string()));
+
+        let missing = interpreter.eval_str("let h = {\"name\": \"x\"}; h").unwrap();
+        assert_eq!(from_object::<Config>(missing).map_err(|err| err.to_string()), Err("missing field `retries`".to_string()));
+    }
+}
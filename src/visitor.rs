@@ -0,0 +1,573 @@
+use std::rc::Rc;
+
+use crate::ast::{Catch, Expression, MatchArm, Pattern, Program, Statement};
+
+/// Native stack a level of any pass over the AST may use before the next
+/// `with_stack`, and the size of the segments added when less is left.
+const RED_ZONE: usize = 256 * 1024;
+const STACK_SEGMENT: usize = 4 * 1024 * 1024;
+
+/// Runs `f`, first moving to a new heap-allocated stack segment if the
+/// current one is nearly used up. The parser and the passes over the AST
+/// recurse once per level of nesting and call this at every level, so
+/// deeply nested programs are only bounded by memory.
+pub fn with_stack<R>(f: impl FnOnce() -> R) -> R {
+    stacker::maybe_grow(RED_ZONE, STACK_SEGMENT, f)
+}
+
+/// Read-only traversal of the AST.
+///
+/// Every method defaults to the matching `walk_*` function, which visits
+/// the children of the node. Override the methods for the nodes a pass
+/// cares about and call `walk_*` from them to keep descending.
+pub trait Visitor {
+    fn visit_program(&mut self, program: &Program) {
+        walk_program(self, program)
+    }
+
+    fn visit_statement(&mut self, statement: &Statement) {
+        walk_statement(self, statement)
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        walk_expression(self, expression)
+    }
+
+    fn visit_pattern(&mut self, pattern: &Pattern) {
+        walk_pattern(self, pattern)
+    }
+}
+
+pub fn walk_program<V: Visitor + ?Sized>(visitor: &mut V, program: &Program) {
+    for statement in &program.statements {
+        visitor.visit_statement(statement);
+    }
+}
+
+pub fn walk_statement<V: Visitor + ?Sized>(visitor: &mut V, statement: &Statement) {
+    with_stack(|| {
+        match statement {
+            Statement::LetStatement{token:_, name, value} => {
+                visitor.visit_pattern(name);
+                visitor.visit_expression(value);
+            },
+            Statement::ReturnStatement{token:_, return_value} => {
+                visitor.visit_expression(return_value);
+            },
+            Statement::ThrowStatement{token:_, value} => {
+                visitor.visit_expression(value);
+            },
+            Statement::ExpressionStatement{token:_, expression} => {
+                visitor.visit_expression(expression);
+            },
+            Statement::BlockStatment{token:_, statements} => {
+                for statement in statements {
+                    visitor.visit_statement(statement);
+                }
+            }
+        }
+    })
+}
+
+pub fn walk_expression<V: Visitor + ?Sized>(visitor: &mut V, expression: &Expression) {
+    with_stack(|| {
+        match expression {
+            Expression::Nope
+            | Expression::Identifier{..}
+            | Expression::Local{..}
+            | Expression::IntegerLiteral{..}
+            | Expression::StringLiteral{..}
+            | Expression::Boolean{..} => {},
+            Expression::ArrayLiteral{token:_, elements} | Expression::TemplateLiteral{token:_, strings:_, values: elements} => {
+                for element in elements {
+                    visitor.visit_expression(element);
+                }
+            },
+            Expression::HashLiteral{token:_, pairs} => {
+                for (key, value) in pairs {
+                    visitor.visit_expression(key);
+                    visitor.visit_expression(value);
+                }
+            },
+            Expression::IndexExpression{token:_, left, index} => {
+                visitor.visit_expression(left);
+                visitor.visit_expression(index);
+            },
+            Expression::SliceExpression{token:_, left, start, end} => {
+                visitor.visit_expression(left);
+                for bound in start.iter().chain(end) {
+                    visitor.visit_expression(bound);
+                }
+            },
+            Expression::Spread{token:_, value} | Expression::Propagate{token:_, value} => {
+                visitor.visit_expression(value);
+            },
+            // The name of a default or keyword argument is a label, not a use.
+            Expression::Named{token:_, name:_, value} => {
+                visitor.visit_expression(value);
+            },
+            Expression::MemberExpression{token:_, object, property:_} => {
+                visitor.visit_expression(object);
+            },
+            Expression::PrefixExpression{token:_, operator:_, right} => {
+                visitor.visit_expression(right);
+            },
+            Expression::InfixExpression{token:_, left, operator:_, right} => {
+                visitor.visit_expression(left);
+                visitor.visit_expression(right);
+            },
+            Expression::IfExpression{token:_, condition, consequence, alternative} => {
+                visitor.visit_expression(condition);
+                visitor.visit_statement(consequence);
+                if let Some(alternative) = alternative {
+                    visitor.visit_statement(alternative);
+                }
+            },
+            Expression::FunctionLiteral{token:_, parameters, body} => {
+                for parameter in parameters {
+                    visitor.visit_expression(parameter);
+                }
+                visitor.visit_statement(body);
+            },
+            Expression::CallExpression{token:_, function, arguments} => {
+                visitor.visit_expression(function);
+                for argument in arguments {
+                    visitor.visit_expression(argument);
+                }
+            },
+            Expression::MatchExpression{token:_, subject, arms} => {
+                visitor.visit_expression(subject);
+                for arm in arms {
+                    visitor.visit_pattern(&arm.pattern);
+                    if let Some(guard) = &arm.guard {
+                        visitor.visit_expression(guard);
+                    }
+                    visitor.visit_statement(&arm.body);
+                }
+            },
+            Expression::TryExpression{token:_, body, catch, finally} => {
+                visitor.visit_statement(body);
+                if let Some(catch) = catch {
+                    if let Some(parameter) = &catch.parameter {
+                        visitor.visit_pattern(parameter);
+                    }
+                    visitor.visit_statement(&catch.body);
+                }
+                if let Some(finally) = finally {
+                    visitor.visit_statement(finally);
+                }
+            }
+        }
+    })
+}
+
+pub fn walk_pattern<V: Visitor + ?Sized>(visitor: &mut V, pattern: &Pattern) {
+    with_stack(|| {
+        match pattern {
+            Pattern::Binding(name) | Pattern::Literal(name) => visitor.visit_expression(name),
+            Pattern::Wildcard(_) => {},
+            Pattern::Array{token:_, elements, rest} => {
+                for element in elements {
+                    visitor.visit_pattern(element);
+                }
+                if let Some(rest) = rest {
+                    visitor.visit_pattern(rest);
+                }
+            },
+            Pattern::Hash{token:_, entries} => {
+                for (_, pattern) in entries {
+                    visitor.visit_pattern(pattern);
+                }
+            },
+            Pattern::Variant{token:_, name:_, value} => {
+                if let Some(value) = value {
+                    visitor.visit_pattern(value);
+                }
+            },
+            Pattern::Or{token:_, alternatives} => {
+                for alternative in alternatives {
+                    visitor.visit_pattern(alternative);
+                }
+            }
+        }
+    })
+}
+
+/// Owning traversal that rebuilds the AST.
+///
+/// The default methods rebuild each node from its folded children, so a
+/// pass only overrides the nodes it rewrites and calls `fold_*_children`
+/// for everything else.
+pub trait Fold {
+    fn fold_program(&mut self, program: Program) -> Program {
+        fold_program_children(self, program)
+    }
+
+    fn fold_statement(&mut self, statement: Statement) -> Statement {
+        fold_statement_children(self, statement)
+    }
+
+    fn fold_expression(&mut self, expression: Expression) -> Expression {
+        fold_expression_children(self, expression)
+    }
+
+    fn fold_pattern(&mut self, pattern: Pattern) -> Pattern {
+        fold_pattern_children(self, pattern)
+    }
+}
+
+pub fn fold_program_children<F: Fold + ?Sized>(folder: &mut F, mut program: Program) -> Program {
+    Program {
+        statements: std::mem::take(&mut program.statements).into_iter().map(|st| folder.fold_statement(st)).collect()
+    }
+}
+
+pub fn fold_statement_children<F: Fold + ?Sized>(folder: &mut F, statement: Statement) -> Statement {
+    with_stack(|| {
+        match statement {
+            Statement::LetStatement{token, name, value} => Statement::LetStatement {
+                token,
+                name: folder.fold_pattern(name),
+                value: folder.fold_expression(value)
+            },
+            Statement::ReturnStatement{token, return_value} => Statement::ReturnStatement {
+                token,
+                return_value: folder.fold_expression(return_value)
+            },
+            Statement::ThrowStatement{token, value} => Statement::ThrowStatement {
+                token,
+                value: folder.fold_expression(value)
+            },
+            Statement::ExpressionStatement{token, expression} => Statement::ExpressionStatement {
+                token,
+                expression: folder.fold_expression(expression)
+            },
+            Statement::BlockStatment{token, statements} => Statement::BlockStatment {
+                token,
+                statements: statements.into_iter().map(|st| folder.fold_statement(st)).collect()
+            }
+        }
+    })
+}
+
+pub fn fold_expression_children<F: Fold + ?Sized>(folder: &mut F, expression: Expression) -> Expression {
+    with_stack(|| {
+        match expression {
+            Expression::ArrayLiteral{token, elements} => Expression::ArrayLiteral {
+                token,
+                elements: fold_expressions(folder, elements)
+            },
+            Expression::TemplateLiteral{token, strings, values} => Expression::TemplateLiteral {
+                token,
+                strings,
+                values: fold_expressions(folder, values)
+            },
+            Expression::HashLiteral{token, pairs} => Expression::HashLiteral {
+                token,
+                pairs: pairs.into_iter()
+                    .map(|(key, value)| (folder.fold_expression(key), folder.fold_expression(value)))
+                    .collect()
+            },
+            Expression::IndexExpression{token, left, index} => Expression::IndexExpression {
+                token,
+                left: Box::new(folder.fold_expression(*left)),
+                index: Box::new(folder.fold_expression(*index))
+            },
+            Expression::SliceExpression{token, left, start, end} => Expression::SliceExpression {
+                token,
+                left: Box::new(folder.fold_expression(*left)),
+                start: start.map(|start| Box::new(folder.fold_expression(*start))),
+                end: end.map(|end| Box::new(folder.fold_expression(*end)))
+            },
+            Expression::Spread{token, value} => Expression::Spread {
+                token,
+                value: Box::new(folder.fold_expression(*value))
+            },
+            Expression::Propagate{token, value} => Expression::Propagate {
+                token,
+                value: Box::new(folder.fold_expression(*value))
+            },
+            // The name of a keyword argument is a label, not a variable.
+            Expression::Named{token, name, value} => Expression::Named {
+                token,
+                name,
+                value: Box::new(folder.fold_expression(*value))
+            },
+            Expression::MemberExpression{token, object, property} => Expression::MemberExpression {
+                token,
+                object: Box::new(folder.fold_expression(*object)),
+                property
+            },
+            Expression::PrefixExpression{token, operator, right} => Expression::PrefixExpression {
+                token,
+                operator,
+                right: Box::new(folder.fold_expression(*right))
+            },
+            Expression::InfixExpression{token, left, operator, right} => Expression::InfixExpression {
+                token,
+                left: Box::new(folder.fold_expression(*left)),
+                operator,
+                right: Box::new(folder.fold_expression(*right))
+            },
+            Expression::IfExpression{token, condition, consequence, alternative} => Expression::IfExpression {
+                token,
+                condition: Box::new(folder.fold_expression(*condition)),
+                consequence: Box::new(folder.fold_statement(*consequence)),
+                alternative: alternative.map(|alternative| Box::new(folder.fold_statement(*alternative)))
+            },
+            Expression::FunctionLiteral{token, parameters, body} => Expression::FunctionLiteral {
+                token,
+                parameters: fold_expressions(folder, parameters),
+                body: Rc::new(folder.fold_statement(unwrap_body(body)))
+            },
+            Expression::CallExpression{token, function, arguments} => Expression::CallExpression {
+                token,
+                function: Box::new(folder.fold_expression(*function)),
+                arguments: fold_expressions(folder, arguments)
+            },
+            Expression::MatchExpression{token, subject, arms} => Expression::MatchExpression {
+                token,
+                subject: Box::new(folder.fold_expression(*subject)),
+                arms: arms.into_iter().map(|arm| MatchArm {
+                    pattern: folder.fold_pattern(arm.pattern),
+                    guard: arm.guard.map(|guard| folder.fold_expression(guard)),
+                    body: Box::new(folder.fold_statement(*arm.body))
+                }).collect()
+            },
+            Expression::TryExpression{token, body, catch, finally} => Expression::TryExpression {
+                token,
+                body: Box::new(folder.fold_statement(*body)),
+                catch: catch.map(|catch| Box::new(Catch {
+                    parameter: catch.parameter.map(|parameter| folder.fold_pattern(parameter)),
+                    body: Box::new(folder.fold_statement(*catch.body))
+                })),
+                finally: finally.map(|finally| Box::new(folder.fold_statement(*finally)))
+            },
+            leaf => leaf
+        }
+    })
+}
+
+pub fn fold_pattern_children<F: Fold + ?Sized>(folder: &mut F, pattern: Pattern) -> Pattern {
+    with_stack(|| {
+        match pattern {
+            Pattern::Binding(name) => Pattern::Binding(folder.fold_expression(name)),
+            Pattern::Literal(literal) => Pattern::Literal(folder.fold_expression(literal)),
+            Pattern::Wildcard(token) => Pattern::Wildcard(token),
+            Pattern::Array{token, elements, rest} => Pattern::Array {
+                token,
+                elements: elements.into_iter().map(|element| folder.fold_pattern(element)).collect(),
+                rest: rest.map(|rest| Box::new(folder.fold_pattern(*rest)))
+            },
+            Pattern::Hash{token, entries} => Pattern::Hash {
+                token,
+                entries: entries.into_iter().map(|(key, pattern)| (key, folder.fold_pattern(pattern))).collect()
+            },
+            Pattern::Variant{token, name, value} => Pattern::Variant {
+                token,
+                name,
+                value: value.map(|value| Box::new(folder.fold_pattern(*value)))
+            },
+            Pattern::Or{token, alternatives} => Pattern::Or {
+                token,
+                alternatives: alternatives.into_iter().map(|alternative| folder.fold_pattern(alternative)).collect()
+            }
+        }
+    })
+}
+
+fn fold_expressions<F: Fold + ?Sized>(folder: &mut F, expressions: Vec<Expression>) -> Vec<Expression> {
+    expressions.into_iter().map(|expression| folder.fold_expression(expression)).collect()
+}
+
+/// Takes a function body out of its `Rc`, cloning it only if function
+/// values made from the literal still share it.
+pub fn unwrap_body(body: Rc<Statement>) -> Statement {
+    Rc::try_unwrap(body).unwrap_or_else(|body| (*body).clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{fold_expression_children, walk_expression, walk_pattern, walk_statement, Fold, Visitor};
+    use crate::ast::{Expression, Pattern, Program, Statement};
+    use crate::interpreter::Interpreter;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::resolver::Resolver;
+
+    /// Every kind of node, once resolved so that locals are `Local`s.
+    const EVERY_NODE: &str = "
+        let [a, ...rest] = [1, 2, 3];
+        let {x, y: why} = {\"x\": 1, \"y\": 2};
+        let f = fn(n, m = 2, ...more) { if (n > m) { return -n; } else { n } };
+        let g = fn(r) { let v = r?; Ok(v) };
+        f(...rest, m = 3);
+        \"${a}!\"[0];
+        \"abc\"[1:];
+        len.name;
+        match (Some(x)) { Some(v) if v > 0 => v, None | Err(_) => 0, 1 => why, [_] => 2, {x} => x };
+        try { throw \"e\" } catch (e) { e } finally { true };
+    ";
+
+    fn parse(source: &str) -> Program {
+        let program = Parser::new(Lexer::new(source.to_string())).parse_program().unwrap();
+        Resolver::new(&Interpreter::new().environment().borrow()).resolve(program).unwrap()
+    }
+
+    #[derive(Default)]
+    struct Kinds(HashSet<&'static str>);
+
+    impl Visitor for Kinds {
+        fn visit_statement(&mut self, statement: &Statement) {
+            self.0.insert(match statement {
+                Statement::LetStatement{..} => "let",
+                Statement::ReturnStatement{..} => "return",
+                Statement::ThrowStatement{..} => "throw",
+                Statement::ExpressionStatement{..} => "expression",
+                Statement::BlockStatment{..} => "block"
+            });
+            walk_statement(self, statement)
+        }
+
+        fn visit_expression(&mut self, expression: &Expression) {
+            self.0.insert(match expression {
+                Expression::Nope => "nope",
+                Expression::Identifier{..} => "identifier",
+                Expression::Local{..} => "local",
+                Expression::IntegerLiteral{..} => "integer",
+                Expression::StringLiteral{..} => "string",
+                Expression::Boolean{..} => "boolean",
+                Expression::ArrayLiteral{..} => "array",
+                Expression::TemplateLiteral{..} => "template",
+                Expression::HashLiteral{..} => "hash",
+                Expression::IndexExpression{..} => "index",
+                Expression::SliceExpression{..} => "slice",
+                Expression::Spread{..} => "spread",
+                Expression::Propagate{..} => "propagate",
+                Expression::Named{..} => "named",
+                Expression::MemberExpression{..} => "member",
+                Expression::PrefixExpression{..} => "prefix",
+                Expression::InfixExpression{..} => "infix",
+                Expression::IfExpression{..} => "if",
+                Expression::FunctionLiteral{..} => "function",
+                Expression::CallExpression{..} => "call",
+                Expression::MatchExpression{..} => "match",
+                Expression::TryExpression{..} => "try"
+            });
+            walk_expression(self, expression)
+        }
+
+        fn visit_pattern(&mut self, pattern: &Pattern) {
+            self.0.insert(match pattern {
+                Pattern::Binding(_) => "binding pattern",
+                Pattern::Wildcard(_) => "wildcard pattern",
+                Pattern::Literal(_) => "literal pattern",
+                Pattern::Array{..} => "array pattern",
+                Pattern::Hash{..} => "hash pattern",
+                Pattern::Variant{..} => "variant pattern",
+                Pattern::Or{..} => "or pattern"
+            });
+            walk_pattern(self, pattern)
+        }
+    }
+
+    #[test]
+    fn visitor_reaches_every_node_kind() {
+        let mut kinds = Kinds::default();
+        kinds.visit_program(&parse(EVERY_NODE));
+        let missing: Vec<&str> = [
+            "let", "return", "throw", "expression", "block",
+            "identifier", "local", "integer", "string", "boolean", "array", "template", "hash", "index", "slice",
+            "spread", "propagate", "named", "member", "prefix", "infix", "if", "function", "call", "match", "try",
+            "binding pattern", "wildcard pattern", "literal pattern", "array pattern", "hash pattern",
+            "variant pattern", "or pattern"
+        ].iter().copied().filter(|kind| !kinds.0.contains(kind)).collect();
+        assert_eq!(missing, Vec::<&str>::new());
+    }
+
+    struct Identity;
+
+    impl Fold for Identity {}
+
+    /// Replaces every integer literal with 0, to check the fold reaches them.
+    struct Zero;
+
+    impl Fold for Zero {
+        fn fold_expression(&mut self, expression: Expression) -> Expression {
+            match fold_expression_children(self, expression) {
+                Expression::IntegerLiteral{token, ..} => Expression::IntegerLiteral{token, value: 0},
+                expression => expression
+            }
+        }
+    }
+
+    struct Integers(Vec<i64>);
+
+    impl Visitor for Integers {
+        fn visit_expression(&mut self, expression: &Expression) {
+            if let Expression::IntegerLiteral{value, ..} = expression {
+                self.0.push(*value);
+            }
+            walk_expression(self, expression)
+        }
+    }
+
+    #[test]
+    fn fold_rebuilds_every_node() {
+        let program = parse(EVERY_NODE);
+        assert_eq!(Identity.fold_program(parse(EVERY_NODE)).statements, program.statements);
+
+        let mut before = Integers(Vec::new());
+        before.visit_program(&program);
+        let mut after = Integers(Vec::new());
+        after.visit_program(&Zero.fold_program(program));
+        assert_eq!(after.0, vec![0; before.0.len()]);
+        assert!(before.0.len() >= 10, "{:?}", before.0);
+    }
+
+    struct Names(Vec<String>);
+
+    impl Visitor for Names {
+        fn visit_expression(&mut self, expression: &Expression) {
+            if let Expression::Identifier{value, ..} = expression {
+                self.0.push(value.clone());
+            }
+            walk_expression(self, expression)
+        }
+    }
+
+    /// Upper-cases every identifier, to check which ones the fold reaches.
+    struct Shout;
+
+    impl Fold for Shout {
+        fn fold_expression(&mut self, expression: Expression) -> Expression {
+            match fold_expression_children(self, expression) {
+                Expression::Identifier{token, value} => Expression::Identifier{token, value: value.to_uppercase()},
+                expression => expression
+            }
+        }
+    }
+
+    #[test]
+    fn keyword_argument_names_are_skipped() {
+        let program = Parser::new(Lexer::new("f(a, key = b);".to_string())).parse_program().unwrap();
+        let mut names = Names(Vec::new());
+        names.visit_program(&program);
+        assert_eq!(names.0, ["f", "a", "b"]);
+
+        let mut folded = Shout.fold_program(program);
+        let mut names = Names(Vec::new());
+        names.visit_program(&folded);
+        assert_eq!(names.0, ["F", "A", "B"]);
+        let arguments = match folded.statements.remove(0) {
+            Statement::ExpressionStatement{expression: Expression::CallExpression{arguments, ..}, ..} => arguments,
+            statement => panic!("not a call: {:?}", statement)
+        };
+        match &arguments[1] {
+            Expression::Named{name, ..} => assert!(matches!(&**name, Expression::Identifier{value, ..} if value == "key")),
+            argument => panic!("not a keyword argument: {:?}", argument)
+        }
+    }
+}